@@ -1,3 +1,3 @@
 #!/usr/bin/env bash
 set -euo pipefail
-echo "Benchmarks stub. Use criterion if needed." 
+cargo bench --bench memory_eviction "$@"
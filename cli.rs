@@ -25,6 +25,9 @@ pub enum Commands {
         compression: Option<String>,
         #[arg(long)]
         name: Option<String>,
+        /// Registered entity kind the room runs.
+        #[arg(long)]
+        entity: Option<String>,
     },
     Enter {
         room_id: String,
@@ -32,6 +35,12 @@ pub enum Commands {
         output: Option<std::path::PathBuf>,
         #[arg(long)]
         readonly: bool,
+        /// Render entity output as text (default) or as JSON lines with level and payload.
+        #[arg(long)]
+        format: Option<String>,
+        /// Hide entity output below this level: debug, info, warn or error.
+        #[arg(long)]
+        min_level: Option<String>,
     },
     List {
         #[arg(long)]
@@ -56,14 +65,91 @@ pub enum Commands {
         #[arg(long)]
         output: std::path::PathBuf,
     },
-    Stats { room_id: String },
+    Stats {
+        room_id: String,
+        /// Show the value, rate and recorded history of one counter instead.
+        #[arg(long)]
+        counter: Option<String>,
+    },
+    /// Rewrite content matching a regex out of a room's memory, entity state and checkpoints.
+    Redact {
+        room_id: String,
+        #[arg(long)]
+        pattern: String,
+        #[arg(long, default_value = "[REDACTED]")]
+        replacement: String,
+    },
+    Memory {
+        room_id: String,
+        #[command(subcommand)]
+        action: MemoryAction,
+    },
     Compare { id1: String, id2: String },
+    /// Reconstruct a room's entity state as of a past unix timestamp.
+    At {
+        room_id: String,
+        timestamp: i64,
+        #[command(subcommand)]
+        view: AtView,
+    },
     Backup { room_id: String, #[arg(long)] output: std::path::PathBuf },
     Restore { path: std::path::PathBuf },
     Batch { #[arg(long)] file: Option<std::path::PathBuf> },
+    Sweep,
+    Fsck {
+        #[arg(long)]
+        repair: bool,
+        #[arg(long)]
+        format: Option<String>,
+    },
     #[cfg(feature="daemon")]
     Daemon,
     #[cfg(feature="daemon")]
     Connect,
     Version,
 }
+
+#[derive(Subcommand, Debug)]
+pub enum MemoryAction {
+    Search {
+        /// Entry type to include; repeatable.
+        #[arg(long = "type")]
+        kinds: Vec<String>,
+        #[arg(long)]
+        since: Option<i64>,
+        #[arg(long)]
+        until: Option<i64>,
+        #[arg(long)]
+        contains: Option<String>,
+        #[arg(long)]
+        regex: Option<String>,
+        /// Metadata predicate: `path`, `path=value` or `path!=value`; repeatable.
+        #[arg(long = "where")]
+        predicates: Vec<String>,
+        #[arg(long)]
+        limit: Option<usize>,
+        #[arg(long, default_value_t = 0)]
+        offset: usize,
+        #[arg(long)]
+        reverse: bool,
+        #[arg(long)]
+        format: Option<String>,
+    },
+    /// Ranked full-text search; every term must appear.
+    Find {
+        terms: String,
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+        #[arg(long)]
+        format: Option<String>,
+    },
+    /// Protect the entries recorded at a unix timestamp from compression and eviction.
+    Pin { timestamp: i64 },
+    Unpin { timestamp: i64 },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum AtView {
+    Inspect,
+    Recall { key: String },
+}
@@ -0,0 +1,343 @@
+use crate::rules::{self, MatchKind};
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+/// Counter used when a counter command names none ("increment counter").
+pub const DEFAULT_COUNTER: &str = "counter";
+
+/// One command of the built-in entity protocol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Remember { key: String, value: String },
+    Recall { key: String },
+    /// `what is <key>`: a recall when the key is stored, free text otherwise.
+    WhatIs { key: String },
+    Forget { key: String },
+    Keys { prefix: Option<String> },
+    Dump,
+    Rename { from: String, to: String },
+    ImportKv { entries: BTreeMap<String, String> },
+    CounterInit { name: String, value: i64 },
+    CounterIncrement { name: String },
+    CounterDecrement { name: String },
+    CounterAdd { name: String, delta: i64 },
+    CounterSet { name: String, value: i64 },
+    CounterReset { name: String },
+    CounterValue { name: String },
+    CounterDelete { name: String },
+    Counters,
+    Pin { timestamp: i64 },
+    Unpin { timestamp: i64 },
+    Status,
+    Report,
+    RuleAdd { priority: i64, kind: MatchKind, pattern: String, response: Option<String> },
+    Rules,
+    RuleRemove { id: u64 },
+    Help { topic: Option<String> },
+}
+
+/// A grammar rule, as listed by `help`.
+pub struct Rule {
+    pub verb: &'static str,
+    pub usage: &'static str,
+    pub aliases: &'static [&'static str],
+    pub summary: &'static str,
+}
+
+/// The protocol, one rule per verb. `parse` accepts exactly these forms.
+pub const GRAMMAR: &[Rule] = &[
+    Rule{ verb: "remember", usage: "remember <key>: <value>", aliases: &["store"], summary: "Store a value under a key. End the line with <<EOF to read the value from the following lines up to EOF." },
+    Rule{ verb: "recall", usage: "recall <key>", aliases: &["retrieve", "what is <key>"], summary: "Emit the value stored under a key. 'what is' a key that is not stored is free text." },
+    Rule{ verb: "forget", usage: "forget <key>", aliases: &[], summary: "Remove a key." },
+    Rule{ verb: "keys", usage: "keys [<prefix>]", aliases: &[], summary: "List stored keys in sorted order, optionally only those starting with a prefix." },
+    Rule{ verb: "dump", usage: "dump", aliases: &[], summary: "Emit every key and value as one JSON object with sorted keys." },
+    Rule{ verb: "rename", usage: "rename <key> <new key>", aliases: &["rename <key> to <new key>"], summary: "Move a value to a key that is not in use." },
+    Rule{ verb: "import", usage: "import kv <json object>", aliases: &[], summary: "Store every key of a JSON object. Accepts <<EOF like remember." },
+    Rule{ verb: "initialize", usage: "initialize counter [<name>] [at <n>]", aliases: &["init"], summary: "Create or overwrite a counter, starting at 0 unless given." },
+    Rule{ verb: "increment", usage: "increment counter [<name>]", aliases: &["inc", "incr"], summary: "Add one to a counter and emit the new value." },
+    Rule{ verb: "decrement", usage: "decrement counter [<name>]", aliases: &["dec", "decr"], summary: "Subtract one from a counter and emit the new value." },
+    Rule{ verb: "add", usage: "add <n> to counter [<name>]", aliases: &[], summary: "Add n to a counter and emit the new value." },
+    Rule{ verb: "subtract", usage: "subtract <n> from counter [<name>]", aliases: &[], summary: "Subtract n from a counter and emit the new value." },
+    Rule{ verb: "set", usage: "set counter [<name>] to <n>", aliases: &[], summary: "Give a counter a value." },
+    Rule{ verb: "reset", usage: "reset counter [<name>]", aliases: &[], summary: "Set a counter back to 0." },
+    Rule{ verb: "show", usage: "show counter [<name>]", aliases: &["get", "what is counter [<name>] value"], summary: "Emit the value of a counter." },
+    Rule{ verb: "delete", usage: "delete counter [<name>]", aliases: &[], summary: "Remove a counter and its history." },
+    Rule{ verb: "counters", usage: "counters", aliases: &["list counters"], summary: "List counters and their values in sorted order." },
+    Rule{ verb: "pin", usage: "pin <timestamp>", aliases: &[], summary: "Protect the memory entries recorded at a timestamp from eviction." },
+    Rule{ verb: "unpin", usage: "unpin <timestamp>", aliases: &[], summary: "Make pinned entries evictable again." },
+    Rule{ verb: "status", usage: "status", aliases: &["system status", "system check", "request status"], summary: "Emit memory usage and room state." },
+    Rule{ verb: "generate", usage: "generate report", aliases: &["report"], summary: "Stream a report on the room's state, history and recommendations." },
+    Rule{ verb: "rule", usage: "rule add [priority <n>] <exact|glob|regex> <pattern> => <response>|silence", aliases: &["rule list", "rules", "rule remove <id>"], summary: "Answer free text matching a pattern with a response, where {kv:<key>}, {counter:<name>} and {input} are filled in, or with silence. Rules run highest priority first." },
+    Rule{ verb: "help", usage: "help [<verb>]", aliases: &["?"], summary: "List the grammar, or the rule for one verb." },
+];
+
+/// Input that starts like a command but does not fit its rule.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("ERROR: PARSE_ERROR ({reason}; usage: {})", rule(verb).map_or("help", |r| r.usage))]
+pub struct ParseError {
+    pub verb: &'static str,
+    pub reason: String,
+}
+
+impl ParseError {
+    fn new(verb: &'static str, reason: impl Into<String>) -> Self {
+        Self { verb, reason: reason.into() }
+    }
+}
+
+/// Look up the rule for a verb or one of its single-word aliases.
+pub fn rule(word: &str) -> Option<&'static Rule> {
+    let word = word.to_ascii_lowercase();
+    GRAMMAR.iter().find(|r| r.verb == word || r.aliases.contains(&word.as_str()))
+}
+
+/// Parse one line of input. `Ok(None)` means the line is not a command at all; the entity
+/// treats it as free text. A line is only an error when it is clearly meant as a command: the
+/// verb with nothing after it where arguments are required, or the verb with its keyword
+/// (`counter`, `kv`, `report`, the `:` of remember, a `rule` subcommand) and bad arguments.
+/// "set the table" or "show me the door" are free text.
+pub fn parse(input: &str) -> Result<Option<Command>, ParseError> {
+    let input = input.trim();
+    let (word, rest) = input.split_once(char::is_whitespace).unwrap_or((input, ""));
+    let rest = rest.trim();
+
+    let cmd = match word.to_ascii_lowercase().as_str() {
+        "remember" | "store" => {
+            let Some((key, value)) = rest.split_once(':') else {
+                return free_text_or_missing("remember", rest, "expected ':' between key and value");
+            };
+            let key = key.trim();
+            if key.is_empty() {
+                return Err(ParseError::new("remember", "missing key"));
+            }
+            Command::Remember { key: key.to_string(), value: value.trim().to_string() }
+        }
+        "recall" | "retrieve" => Command::Recall { key: required_key("recall", rest)? },
+        "forget" => Command::Forget { key: required_key("forget", rest)? },
+        "keys" => Command::Keys { prefix: (!rest.is_empty()).then(|| rest.to_string()) },
+        "dump" if rest.is_empty() => Command::Dump,
+        "rename" => {
+            let pair = match rest.split_once(" to ") {
+                Some((from, to)) => Some((from.trim(), to.trim())),
+                None => match rest.split_whitespace().collect::<Vec<_>>()[..] {
+                    [from, to] => Some((from, to)),
+                    _ => None,
+                },
+            };
+            match pair {
+                Some((from, to)) if !from.is_empty() && !to.is_empty() => Command::Rename { from: from.to_string(), to: to.to_string() },
+                _ => return free_text_or_missing("rename", rest, "expected two keys"),
+            }
+        }
+        "import" => match strip_word(rest, "kv") {
+            Some(json) => Command::ImportKv { entries: import_entries(json)? },
+            None => return free_text_or_missing("import", rest, "expected 'kv'"),
+        },
+        "what" => {
+            let Some(subject) = strip_word(rest, "is") else {
+                return Ok(None);
+            };
+            match strip_word(subject, "counter").and_then(|name| strip_last_word(name, "value")) {
+                Some(name) => Command::CounterValue { name: counter_name(name) },
+                None if subject.is_empty() => return Ok(None),
+                None => Command::WhatIs { key: subject.to_string() },
+            }
+        }
+        "initialize" | "init" => {
+            let Some(args) = counter_args("initialize", rest)? else { return Ok(None) };
+            let missing = || ParseError::new("initialize", "expected an integer after 'at'");
+            if strip_last_word(args, "at").is_some() {
+                return Err(missing());
+            }
+            let (head, n) = args.rsplit_once(char::is_whitespace).unwrap_or(("", args));
+            match strip_last_word(head, "at") {
+                Some(name) => Command::CounterInit { name: counter_name(name), value: n.parse().map_err(|_| missing())? },
+                None => Command::CounterInit { name: counter_name(args), value: 0 },
+            }
+        }
+        "increment" | "inc" | "incr" | "decrement" | "dec" | "decr" | "reset" | "show" | "get" | "delete" => {
+            let verb = rule(word).map_or("help", |r| r.verb);
+            let Some(args) = counter_args(verb, rest)? else { return Ok(None) };
+            let name = counter_name(args);
+            match verb {
+                "increment" => Command::CounterIncrement { name },
+                "decrement" => Command::CounterDecrement { name },
+                "reset" => Command::CounterReset { name },
+                "show" => Command::CounterValue { name },
+                _ => Command::CounterDelete { name },
+            }
+        }
+        "add" | "subtract" => {
+            let verb = if word.eq_ignore_ascii_case("add") { "add" } else { "subtract" };
+            let (n, target) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            let Some(target) = strip_word(target, if verb == "add" { "to" } else { "from" }) else {
+                return free_text_or_missing(verb, rest, "expected a counter");
+            };
+            let Some(args) = counter_args(verb, target)? else { return Ok(None) };
+            let n = number(verb, n)?;
+            let delta = if verb == "add" { Some(n) } else { n.checked_neg() };
+            let delta = delta.ok_or_else(|| ParseError::new(verb, "amount out of range"))?;
+            Command::CounterAdd { name: counter_name(args), delta }
+        }
+        "set" => {
+            let Some(args) = counter_args("set", rest)? else { return Ok(None) };
+            let (head, n) = args.rsplit_once(char::is_whitespace).unwrap_or(("", args));
+            let name = strip_last_word(head, "to").ok_or_else(|| ParseError::new("set", "expected 'to <n>'"))?;
+            Command::CounterSet { name: counter_name(name), value: number("set", n)? }
+        }
+        "counters" if rest.is_empty() => Command::Counters,
+        "list" if rest.eq_ignore_ascii_case("counters") => Command::Counters,
+        "pin" | "unpin" => {
+            let verb = if word.eq_ignore_ascii_case("pin") { "pin" } else { "unpin" };
+            let Ok(timestamp) = rest.parse() else {
+                return free_text_or_missing(verb, rest, "expected a timestamp");
+            };
+            if verb == "pin" { Command::Pin { timestamp } } else { Command::Unpin { timestamp } }
+        }
+        "status" if rest.is_empty() => Command::Status,
+        "system" if rest.eq_ignore_ascii_case("status") || rest.eq_ignore_ascii_case("check") => Command::Status,
+        "request" if rest.eq_ignore_ascii_case("status") => Command::Status,
+        "generate" => match strip_word(rest, "report") {
+            Some("") => Command::Report,
+            _ => return free_text_or_missing("generate", rest, "expected 'report'"),
+        },
+        "report" if rest.is_empty() => Command::Report,
+        "rules" if rest.is_empty() => Command::Rules,
+        "list" if rest.eq_ignore_ascii_case("rules") => Command::Rules,
+        "rule" => match rest.split_once(char::is_whitespace).unwrap_or((rest, "")) {
+            (sub, "") if sub.eq_ignore_ascii_case("list") => Command::Rules,
+            (sub, id) if sub.eq_ignore_ascii_case("remove") => {
+                Command::RuleRemove { id: id.trim().parse().map_err(|_| ParseError::new("rule", "expected a rule id"))? }
+            }
+            (sub, args) if sub.eq_ignore_ascii_case("add") => rule_add(args.trim())?,
+            _ => return free_text_or_missing("rule", rest, "expected 'add', 'list' or 'remove'"),
+        },
+        "help" | "?" => Command::Help { topic: (!rest.is_empty()).then(|| rest.to_string()) },
+        _ => return Ok(None),
+    };
+    Ok(Some(cmd))
+}
+
+/// Lines emitted for `help`: the whole grammar, or the rule for one verb.
+pub fn help(topic: Option<&str>) -> Vec<String> {
+    let rules: Vec<&Rule> = match topic {
+        Some(t) => match rule(t) {
+            Some(r) => vec![r],
+            None => return vec![format!("Unknown command '{}'. Try 'help'.", t)],
+        },
+        None => GRAMMAR.iter().collect(),
+    };
+    rules
+        .into_iter()
+        .map(|r| {
+            if r.aliases.is_empty() {
+                format!("{} - {}", r.usage, r.summary)
+            } else {
+                format!("{} - {} Aliases: {}.", r.usage, r.summary, r.aliases.join(", "))
+            }
+        })
+        .collect()
+}
+
+/// Verbs whose last argument may span several lines.
+const BLOCK_VERBS: &[&str] = &["remember", "import"];
+
+/// A `remember` or `import kv` line ending in `<<TERMINATOR` opens a block: the lines that
+/// follow, up to one equal to the terminator, belong to the command. Returns the line without
+/// the marker, and the terminator.
+pub fn heredoc(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim();
+    let verb = line.split_whitespace().next().and_then(rule)?.verb;
+    let (head, marker) = line.rsplit_once(char::is_whitespace)?;
+    let terminator = marker.strip_prefix("<<")?;
+    if !BLOCK_VERBS.contains(&verb) || terminator.is_empty() || !terminator.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return None;
+    }
+    Some((head.trim_end(), terminator))
+}
+
+/// Values of an `import kv` object. Scalars are stored in their JSON text form; nested
+/// values are rejected so a dump can always be imported back unchanged.
+fn import_entries(json: &str) -> Result<BTreeMap<String, String>, ParseError> {
+    let value: serde_json::Value = serde_json::from_str(json).map_err(|e| ParseError::new("import", format!("invalid JSON: {}", e)))?;
+    let serde_json::Value::Object(map) = value else {
+        return Err(ParseError::new("import", "expected a JSON object"));
+    };
+    map.into_iter()
+        .map(|(k, v)| match v {
+            serde_json::Value::String(s) => Ok((k, s)),
+            serde_json::Value::Array(_) | serde_json::Value::Object(_) => Err(ParseError::new("import", format!("value of '{}' is not a scalar", k))),
+            other => Ok((k, other.to_string())),
+        })
+        .collect()
+}
+
+/// Arguments of `rule add`. The pattern runs up to the first ` => `; regex patterns are
+/// compiled here so a broken one is refused rather than stored.
+fn rule_add(args: &str) -> Result<Command, ParseError> {
+    let (priority, args) = match strip_word(args, "priority") {
+        Some(rest) => {
+            let (n, rest) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            (number("rule", n)?, rest.trim())
+        }
+        None => (0, args),
+    };
+    let (kind, args) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let kind = rules::parse_match_kind(kind).ok_or_else(|| ParseError::new("rule", "expected 'exact', 'glob' or 'regex'"))?;
+    let (pattern, response) = args.split_once(" => ").ok_or_else(|| ParseError::new("rule", "expected ' => ' between pattern and response"))?;
+    let pattern = pattern.trim();
+    if pattern.is_empty() {
+        return Err(ParseError::new("rule", "missing pattern"));
+    }
+    if kind == MatchKind::REGEX {
+        rules::compile(pattern).map_err(|_| ParseError::new("rule", format!("invalid regex '{}'", pattern)))?;
+    }
+    let response = response.trim();
+    let response = (!response.eq_ignore_ascii_case(rules::SILENCE)).then(|| response.to_string());
+    Ok(Command::RuleAdd { priority, kind, pattern: pattern.to_string(), response })
+}
+
+fn required_key(verb: &'static str, rest: &str) -> Result<String, ParseError> {
+    if rest.is_empty() {
+        return Err(ParseError::new(verb, "missing key"));
+    }
+    Ok(rest.to_string())
+}
+
+/// Everything after the `counter` keyword every counter verb requires. A verb alone is an
+/// error; a verb followed by anything else is free text.
+fn counter_args<'a>(verb: &'static str, rest: &'a str) -> Result<Option<&'a str>, ParseError> {
+    if rest.is_empty() {
+        return Err(ParseError::new(verb, "expected 'counter'"));
+    }
+    Ok(strip_word(rest, "counter"))
+}
+
+/// For a verb whose arguments did not fit: a verb alone is an error, anything after it makes
+/// the line free text.
+fn free_text_or_missing(verb: &'static str, rest: &str, reason: &str) -> Result<Option<Command>, ParseError> {
+    if rest.is_empty() {
+        return Err(ParseError::new(verb, reason));
+    }
+    Ok(None)
+}
+
+fn counter_name(name: &str) -> String {
+    if name.is_empty() { DEFAULT_COUNTER.to_string() } else { name.to_string() }
+}
+
+fn number(verb: &'static str, s: &str) -> Result<i64, ParseError> {
+    s.parse().map_err(|_| ParseError::new(verb, format!("expected an integer, got '{}'", s)))
+}
+
+/// Strip a leading keyword, case-insensitively, returning the trimmed remainder.
+fn strip_word<'a>(s: &'a str, word: &str) -> Option<&'a str> {
+    let (head, rest) = s.split_once(char::is_whitespace).unwrap_or((s, ""));
+    head.eq_ignore_ascii_case(word).then(|| rest.trim())
+}
+
+/// Strip a trailing keyword, case-insensitively, returning the trimmed remainder.
+fn strip_last_word<'a>(s: &'a str, word: &str) -> Option<&'a str> {
+    let (rest, tail) = s.rsplit_once(char::is_whitespace).unwrap_or(("", s));
+    tail.eq_ignore_ascii_case(word).then(|| rest.trim())
+}
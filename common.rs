@@ -0,0 +1,39 @@
+//! Fixtures shared by the integration tests. Each test binary uses only some of them.
+#![allow(dead_code)]
+
+use backrooms_terminal::config::Config;
+use backrooms_terminal::room::{Room, RoomConfig};
+use backrooms_terminal::session::{new_room, process_input, room_config_from};
+
+/// A room created at `now` with `rc`, by a placeholder user and host.
+pub fn room(id: &str, rc: RoomConfig, now: i64) -> Room {
+    new_room(id.to_string(), rc, now, "u".to_string(), "h".to_string())
+}
+
+/// A room with `cfg`'s defaults, created at 100.
+pub fn room_from(id: &str, cfg: &Config) -> Room {
+    room(id, room_config_from(cfg), 100)
+}
+
+/// Feeds inputs to a room. A plain struct rather than a closure, so the borrow of the room
+/// ends at the last input and the test can inspect it afterwards.
+pub struct Runner<'a> {
+    pub room: &'a mut Room,
+    pub cfg: &'a Config,
+}
+
+pub fn runner<'a>(room: &'a mut Room, cfg: &'a Config) -> Runner<'a> {
+    Runner { room, cfg }
+}
+
+impl Runner<'_> {
+    /// Run `input` at 101, returning what the entity emitted.
+    pub fn input(&mut self, input: &str) -> Option<String> {
+        process_input(self.room, input, 101, self.cfg)
+    }
+
+    /// Run `input` at `now`, returning what the entity emitted; it must emit something.
+    pub fn at(&mut self, input: &str, now: i64) -> String {
+        process_input(self.room, input, now, self.cfg).unwrap()
+    }
+}
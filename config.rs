@@ -1,4 +1,7 @@
+use crate::memory::{self, default_pinned_capacity, RetentionPolicy};
+use crate::redact::RedactionRule;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -8,6 +11,11 @@ pub struct Config {
     pub logging: LoggingConfig,
     pub entity: EntityConfig,
     pub daemon: DaemonConfig,
+    #[serde(default)]
+    pub lifecycle: LifecycleConfig,
+    /// Applied to every input before it is recorded, in order.
+    #[serde(default)]
+    pub redaction: Vec<RedactionRule>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -37,6 +45,19 @@ pub struct LimitsConfig {
     pub entity_timeout: u64,
     pub input_queue_depth: usize,
     pub memory_entries_max: usize,
+    /// Cap on the sum of memory usage across all rooms.
+    #[serde(default = "default_max_total_memory")]
+    pub max_total_memory: u64,
+    /// Per-entry-type retention rules copied into new rooms.
+    #[serde(default)]
+    pub retention: RetentionPolicy,
+    /// Per-room cap on bytes held by pinned memory entries.
+    #[serde(default = "default_pinned_capacity")]
+    pub max_pinned_memory: u64,
+}
+
+fn default_max_total_memory() -> u64 {
+    16 * 1024 * 1024 * 1024
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -52,6 +73,37 @@ pub struct EntityConfig {
     pub response_buffer: usize,
     pub memory_compression_threshold: f64,
     pub enable_observations: bool,
+    /// Samples of each counter's value kept for `stats --counter`; 0 keeps none.
+    #[serde(default)]
+    pub counter_history: usize,
+    /// Entity kinds served by external executables, registered under their key.
+    #[serde(default)]
+    pub processes: BTreeMap<String, ProcessEntityConfig>,
+}
+
+/// An executable spawned once per room, speaking the JSON line protocol on stdin/stdout.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProcessEntityConfig {
+    /// Program followed by its arguments.
+    pub command: Vec<String>,
+    /// RLIMIT_CPU: CPU seconds the child may use over its lifetime; 0 leaves it unset.
+    #[serde(default = "default_process_cpu_seconds")]
+    pub cpu_seconds: u64,
+    /// RLIMIT_AS in bytes; 0, the default, leaves it unset. Address space is not memory use:
+    /// runtimes that reserve large mappings need far more than they touch.
+    #[serde(default)]
+    pub address_space: u64,
+    /// RLIMIT_NOFILE; 0 leaves it unset.
+    #[serde(default = "default_process_open_files")]
+    pub open_files: u64,
+}
+
+fn default_process_cpu_seconds() -> u64 {
+    3600
+}
+
+fn default_process_open_files() -> u64 {
+    64
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -63,6 +115,22 @@ pub struct DaemonConfig {
     pub connection_timeout: u64,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LifecycleConfig {
+    /// Seconds without input before an ACTIVE room drops to IDLE.
+    pub idle_after: u64,
+    /// Seconds without input before a room is suspended. Disabled when unset.
+    pub suspend_after: Option<u64>,
+    /// How often the daemon applies the idle policy, in seconds.
+    pub sweep_interval: u64,
+}
+
+impl Default for LifecycleConfig {
+    fn default() -> Self {
+        Self { idle_after: 300, suspend_after: None, sweep_interval: 60 }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "UPPERCASE")]
 pub enum Backend {
@@ -92,11 +160,16 @@ impl Config {
         for p in candidates {
             if p.exists() {
                 let raw = std::fs::read_to_string(&p)?;
-                return Ok(serde_json::from_str(&raw)?);
+                return Self::parse(&raw);
             }
         }
 
-        let raw = include_str!("../config/default.json");
-        Ok(serde_json::from_str(raw)?)
+        Self::parse(include_str!("../config/default.json"))
+    }
+
+    fn parse(raw: &str) -> anyhow::Result<Self> {
+        let cfg: Self = serde_json::from_str(raw)?;
+        memory::check_compression(&cfg.persistence.compression)?;
+        Ok(cfg)
     }
 }
@@ -1,17 +1,33 @@
 #[cfg(feature="daemon")]
 use crate::config::Config;
 #[cfg(feature="daemon")]
-use crate::cli::{Cli, Commands};
-#[cfg(feature="daemon")]
 use crate::config::{Backend};
 #[cfg(feature="daemon")]
+use crate::entity::{Output, OutputSink};
+#[cfg(feature="daemon")]
+use crate::error::RoomError;
+#[cfg(feature="daemon")]
+use crate::input;
+#[cfg(feature="daemon")]
 use crate::persistence::{Persistence};
 #[cfg(feature="daemon")]
+use crate::room::{Room, RoomState};
+#[cfg(feature="daemon")]
+use crate::session;
+#[cfg(feature="daemon")]
 use anyhow::Context;
 #[cfg(feature="daemon")]
+use std::net::SocketAddr;
+#[cfg(feature="daemon")]
+use std::sync::Arc;
+#[cfg(feature="daemon")]
+use std::time::Duration;
+#[cfg(feature="daemon")]
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 #[cfg(feature="daemon")]
 use tokio::net::{TcpListener, TcpStream};
+#[cfg(feature="daemon")]
+use tokio::sync::{mpsc, Mutex};
 
 #[cfg(feature="daemon")]
 fn persistence_from_cfg(cfg: &Config) -> anyhow::Result<Box<dyn Persistence>> {
@@ -33,12 +49,33 @@ pub fn run(cfg: Config) -> anyhow::Result<()> {
         let persistence = persistence_from_cfg(&cfg)?;
         persistence.init()?;
 
+        let sweep_cfg = cfg.clone();
+        tokio::spawn(async move {
+            let mut tick = tokio::time::interval(Duration::from_secs(sweep_cfg.lifecycle.sweep_interval.max(1)));
+            loop {
+                tick.tick().await;
+                // The sweep loads and saves every room, so it runs on the blocking pool.
+                let cfg = sweep_cfg.clone();
+                let swept = tokio::task::spawn_blocking(move || {
+                    persistence_from_cfg(&cfg).and_then(|p| session::sweep(&*p, &cfg.lifecycle, session::now_ts()))
+                }).await.map_err(anyhow::Error::from).and_then(|r| r);
+                match swept {
+                    Ok(changes) => {
+                        for (id, from, to) in changes {
+                            eprintln!("[INFO] room {} {:?} -> {:?}", id, from, to);
+                        }
+                    }
+                    Err(e) => eprintln!("[WARN] sweep failed: {}", e),
+                }
+            }
+        });
+
         loop {
             let (socket, addr) = listener.accept().await?;
             let cfg = cfg.clone();
             let persistence = persistence_from_cfg(&cfg)?;
             tokio::spawn(async move {
-                if let Err(e) = handle_client(socket, persistence).await {
+                if let Err(e) = handle_client(socket, addr, persistence, cfg).await {
                     eprintln!("[WARN] client {} error: {}", addr, e);
                 }
             });
@@ -47,37 +84,142 @@ pub fn run(cfg: Config) -> anyhow::Result<()> {
 }
 
 #[cfg(feature="daemon")]
-async fn handle_client(socket: TcpStream, persistence: Box<dyn Persistence>) -> anyhow::Result<()> {
-    let (r, mut w) = socket.into_split();
-    let mut reader = BufReader::new(r);
-    w.write_all(b"CONNECTED
-").await?;
-    w.write_all(b"> ").await?;
+async fn handle_client(socket: TcpStream, addr: SocketAddr, persistence: Box<dyn Persistence>, cfg: Config) -> anyhow::Result<()> {
+    let (r, w) = socket.into_split();
+    let w = Arc::new(Mutex::new(w));
+    w.lock().await.write_all(b"CONNECTED\n> ").await?;
+
+    // Inputs wait here while the previous one is processed. Overflow is rejected, never buffered.
+    let depth = cfg.limits.input_queue_depth.max(1);
+    // Lines are queued as raw bytes; preprocessing decides what reaches the room.
+    let (tx, mut rx) = mpsc::channel::<Vec<u8>>(depth);
+    let reader_w = w.clone();
+    let reader = tokio::spawn(async move {
+        let mut reader = BufReader::new(r);
+        let mut line = Vec::new();
+        while reader.read_until(b'\n', &mut line).await? != 0 {
+            let cmd = std::mem::take(&mut line);
+            match tx.try_send(cmd) {
+                Ok(()) => {}
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    let err = RoomError::QueueFull { max: depth };
+                    reader_w.lock().await.write_all(format!("{}\n", err).as_bytes()).await?;
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => break,
+            }
+        }
+        anyhow::Ok(())
+    });
 
-    let mut line = String::new();
-    while reader.read_line(&mut line).await? != 0 {
-        let cmd = line.trim();
-        line.clear();
+    // Session output goes through a writer task, so entity lines reach the client while
+    // the entity is still running.
+    let (out_tx, mut out_rx) = mpsc::unbounded_channel::<String>();
+    let writer_w = w.clone();
+    let writer = tokio::spawn(async move {
+        while let Some(text) = out_rx.recv().await {
+            writer_w.lock().await.write_all(text.as_bytes()).await?;
+        }
+        anyhow::Ok(())
+    });
+    let mut client = ClientSession { room: None, persistence, cfg, out: ChannelLines(out_tx) };
 
-        if cmd.eq_ignore_ascii_case("disconnect") {
-            w.write_all(b"SESSION CLOSED
-").await?;
+    while let Some(raw) = rx.recv().await {
+        if raw.trim_ascii().eq_ignore_ascii_case(b"disconnect") {
+            client.out.emit("SESSION CLOSED");
             break;
         }
-        // Minimal: accept "list" only as proof-of-life.
-        if cmd.eq_ignore_ascii_case("list") {
-            let rooms = persistence.list_rooms()?;
-            w.write_all(format!("ROOMS {}
-", rooms.len()).as_bytes()).await?;
-            w.write_all(b"> ").await?;
-            continue;
+        // Entities and persistence block, an entity for up to its timeout, so each command
+        // runs on the blocking pool and the runtime stays free to flush output as it streams.
+        client = tokio::task::spawn_blocking(move || {
+            let ClientSession { room, persistence, cfg, out } = &mut client;
+            match dispatch(&raw, room, &**persistence, cfg, addr, out) {
+                Ok(lines) => lines.iter().for_each(|l| out.emit(l)),
+                Err(e) => out.emit(&e.to_string()),
+            }
+            let _ = out.0.send("> ".to_string());
+            client
+        }).await?;
+    }
+    reader.abort();
+    let ClientSession { room, persistence, out, .. } = client;
+    drop(out);
+    writer.await??;
+    if let Some(mut r) = room {
+        r.transition(RoomState::IDLE, "disconnect", session::now_ts());
+        persistence.save_room(&r)?;
+    }
+    Ok(())
+}
+
+/// What one client's commands work on, handed to the blocking pool and back for each command.
+#[cfg(feature="daemon")]
+struct ClientSession {
+    room: Option<Room>,
+    persistence: Box<dyn Persistence>,
+    cfg: Config,
+    out: ChannelLines,
+}
+
+/// Queues each line for the session's writer task.
+#[cfg(feature="daemon")]
+struct ChannelLines(mpsc::UnboundedSender<String>);
+
+#[cfg(feature="daemon")]
+impl OutputSink for ChannelLines {
+    fn send(&mut self, output: Output) {
+        let _ = self.0.send(format!("{}\n", output.text));
+    }
+}
+
+/// Handle one session command: room management outside a room, entity input inside one.
+/// Entity output is streamed to `out`; management commands return their lines.
+#[cfg(feature="daemon")]
+fn dispatch(raw: &[u8], room: &mut Option<Room>, persistence: &dyn Persistence, cfg: &Config, addr: SocketAddr, out: &mut dyn OutputSink) -> anyhow::Result<Vec<String>> {
+    let cmd = raw.trim_ascii();
+    if let Some(r) = room.as_mut() {
+        if cmd.eq_ignore_ascii_case(b"exit") || cmd.eq_ignore_ascii_case(b"quit") {
+            r.transition(RoomState::IDLE, "exit", session::now_ts());
+            persistence.save_room(r)?;
+            *room = None;
+            return Ok(vec!["EXITING ROOM".to_string(), "ROOM STATE: IDLE".to_string()]);
         }
+        let before = r.memory.usage;
+        session::stream_raw_input(r, raw, session::now_ts(), cfg, out);
+        if r.memory.usage > before {
+            session::enforce_global_memory(persistence, r, cfg, session::now_ts())?;
+        }
+        persistence.save_room(r)?;
+        return Ok(vec![]);
+    }
 
-        w.write_all(b"UNKNOWN
-").await?;
-        w.write_all(b"> ").await?;
+    let cmd = match input::preprocess(cmd, cfg.limits.max_input_size as u64) {
+        Ok(cmd) => cmd,
+        Err(reason) => return Ok(vec![reason.error(cfg.limits.max_input_size as u64).to_string()]),
+    };
+    let parts: Vec<&str> = cmd.split_whitespace().collect();
+    match parts.as_slice() {
+        [c] if c.eq_ignore_ascii_case("list") => {
+            let rooms = persistence.list_rooms()?;
+            Ok(vec![format!("ROOMS {}", rooms.len())])
+        }
+        [c] if c.eq_ignore_ascii_case("create") => {
+            let rc = session::room_config_from(cfg);
+            let id = session::make_room_id(&format!("daemon:{}", addr));
+            let new = session::new_room(id.clone(), rc, session::now_ts(), "daemon".to_string(), addr.to_string());
+            session::check_create_limits(persistence, &cfg.limits, &new)?;
+            persistence.save_room(&new)?;
+            Ok(vec![format!("ROOM CREATED: {}", id), "STATE: ACTIVE".to_string(), "ENTITY: INITIALIZED".to_string()])
+        }
+        [c, id] if c.eq_ignore_ascii_case("enter") => {
+            let mut loaded = persistence.load_room(id)?;
+            session::check_enterable(&loaded)?;
+            session::enter_room(&mut loaded, cfg, session::now_ts());
+            persistence.save_room(&loaded)?;
+            *room = Some(loaded);
+            Ok(vec!["ENTERING ROOM".to_string()])
+        }
+        _ => Ok(vec!["UNKNOWN".to_string()]),
     }
-    Ok(())
 }
 
 #[cfg(feature="daemon")]
@@ -1,105 +1,562 @@
+use crate::command::{self, Command};
+use crate::error::RoomError;
 use crate::memory::{EntryType, MemoryEntry};
-use crate::room::Room;
+use crate::room::{Room, RoomState};
+use crate::rules::{self, ResponseRule};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, OnceLock, RwLock};
+
+/// Kind of entity a room runs when none is chosen at creation.
+pub const DEFAULT_ENTITY: &str = "default";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EntityState {
     pub kv: HashMap<String, String>,
     pub counters: HashMap<String, i64>,
+    /// Version of the entity implementation that initialized the room.
     pub version: String,
+    /// Free-form state for entities that keep more than kv pairs and counters.
+    #[serde(default)]
+    pub custom: serde_json::Value,
+    /// Recent values of each counter, oldest first, bounded by `RoomConfig.counter_history`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub counter_history: BTreeMap<String, VecDeque<CounterSample>>,
+    /// Multi-line command still being read.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pending: Option<PendingBlock>,
+    /// Responses to free text. Rooms saved before rules existed get the defaults.
+    #[serde(default = "rules::default_rules")]
+    pub rules: Vec<ResponseRule>,
+}
+
+/// A counter value and when it was written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CounterSample {
+    pub timestamp: i64,
+    pub value: i64,
+}
+
+/// Average change per second between the oldest and newest samples of a series.
+pub fn counter_rate(series: &VecDeque<CounterSample>) -> Option<f64> {
+    let (first, last) = (series.front()?, series.back()?);
+    let elapsed = last.timestamp - first.timestamp;
+    (elapsed > 0).then(|| (last.value as f64 - first.value as f64) / elapsed as f64)
+}
+
+/// A command opened with `<<TERMINATOR`, collecting lines until one equals the terminator.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingBlock {
+    pub head: String,
+    pub terminator: String,
+    pub lines: Vec<String>,
+}
+
+impl PendingBlock {
+    /// Size of the input the block will run as.
+    pub fn size(&self) -> usize {
+        self.head.len() + self.lines.iter().map(|l| l.len() + 1).sum::<usize>()
+    }
 }
 
 impl Default for EntityState {
     fn default() -> Self {
-        Self { kv: HashMap::new(), counters: HashMap::new(), version: "2.1.0".to_string() }
+        Self { kv: HashMap::new(), counters: HashMap::new(), version: BuiltinEntity::VERSION.to_string(), custom: serde_json::Value::Null, counter_history: BTreeMap::new(), pending: None, rules: rules::default_rules() }
+    }
+}
+
+/// Behavior behind a room. Implementations hold no per-room state of their own: everything
+/// that must survive a restart lives in `room.entity_state`, so one instance serves every room
+/// of its kind.
+pub trait Entity: Send + Sync {
+    /// Registry name, persisted in `RoomConfig.entity`.
+    fn name(&self) -> &str;
+    fn version(&self) -> &str;
+    /// Called once when a room of this kind is created.
+    fn init(&self, room: &mut Room, _now: i64) {
+        room.entity_state.version = self.version().to_string();
+    }
+    /// Handle one line of input, returning the line to emit, if any. Implementations record
+    /// the INPUT entry themselves so they control how it is stored.
+    fn handle_input(&self, room: &mut Room, input: &str, now: i64) -> Option<String>;
+    /// Handle one line of input, emitting output to `out` line by line as it is produced.
+    /// Sessions call this; the default emits the result of `handle_input` once it returns.
+    /// An error discards the input as a panic would: the room is put back as it was and
+    /// `restart` is called. Entities report their own failures as `EntityCrashed`.
+    fn stream_input(&self, room: &mut Room, input: &str, now: i64, out: &mut dyn OutputSink) -> Result<(), RoomError> {
+        if let Some(text) = self.handle_input(room, input, now) {
+            for line in text.lines() {
+                out.emit(line);
+            }
+        }
+        Ok(())
+    }
+    /// Whether replaying recorded inputs through `handle_input` rebuilds the entity state, as
+    /// `room.exe at` does. Entities with side effects outside the room return false.
+    fn replayable(&self) -> bool {
+        true
+    }
+    /// Called after every lifecycle transition, including ones that leave the state unchanged.
+    fn on_state_change(&self, _room: &mut Room, _from: RoomState, _to: RoomState, _now: i64) {}
+    /// Called before the room is destroyed.
+    fn shutdown(&self, _room: &mut Room, _now: i64) {}
+    /// Called from the session while `stream_input` is still running for the room on another
+    /// thread, once it has overrun the room's timeout. Entities that can cut the input short
+    /// should make `stream_input` return; the session waits for it either way.
+    fn abort(&self, _room_id: &str) {}
+    /// Called after an invocation timed out or panicked. The room is back to its state from before
+    /// the input; the entity drops anything it still holds for that input.
+    fn restart(&self, _room: &mut Room, _now: i64) {}
+}
+
+/// Severity of an output line. Transports can filter on it; text rendering does not show it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum OutputLevel {
+    DEBUG,
+    INFO,
+    WARN,
+    ERROR,
+}
+
+pub fn parse_output_level(s: &str) -> anyhow::Result<OutputLevel> {
+    serde_json::from_value(serde_json::Value::String(s.to_uppercase()))
+        .map_err(|_| anyhow::anyhow!("unknown output level: {}", s))
+}
+
+/// One line of entity output, with an optional structured payload.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Output {
+    pub level: OutputLevel,
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload: Option<serde_json::Value>,
+}
+
+impl Output {
+    pub fn new(level: OutputLevel, text: impl Into<String>) -> Self {
+        Self { level, text: text.into(), payload: None }
+    }
+
+    /// A plain line: ERROR when it is a protocol error line, INFO otherwise.
+    pub fn line(text: &str) -> Self {
+        let error = text.starts_with("ERROR: ") || text.starts_with("FATAL: ");
+        Self::new(if error { OutputLevel::ERROR } else { OutputLevel::INFO }, text)
+    }
+
+    pub fn with_payload(mut self, payload: serde_json::Value) -> Self {
+        self.payload = Some(payload);
+        self
+    }
+}
+
+/// Receives entity output one line at a time. Transports write each line out as soon as it
+/// is emitted.
+pub trait OutputSink {
+    fn send(&mut self, output: Output);
+
+    /// Send a plain line, leveled by `Output::line`.
+    fn emit(&mut self, line: &str) {
+        self.send(Output::line(line));
+    }
+}
+
+/// Collects the text of each line, for callers that want the whole response at once.
+impl OutputSink for Vec<String> {
+    fn send(&mut self, output: Output) {
+        self.push(output.text);
+    }
+}
+
+/// Named entity implementations a room can be created with.
+#[derive(Clone, Default)]
+pub struct EntityRegistry {
+    entities: BTreeMap<String, Arc<dyn Entity>>,
+}
+
+impl EntityRegistry {
+    /// A registry holding the entities that ship with room.exe.
+    pub fn with_builtins() -> Self {
+        let mut r = Self::default();
+        r.register(Arc::new(BuiltinEntity));
+        r
+    }
+
+    /// Add `entity` under its name, replacing any entity registered under the same name.
+    pub fn register(&mut self, entity: Arc<dyn Entity>) {
+        self.entities.insert(entity.name().to_string(), entity);
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn Entity>> {
+        self.entities.get(name).cloned()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.entities.keys().cloned().collect()
+    }
+}
+
+fn registry() -> &'static RwLock<EntityRegistry> {
+    static REGISTRY: OnceLock<RwLock<EntityRegistry>> = OnceLock::new();
+    REGISTRY.get_or_init(|| RwLock::new(EntityRegistry::with_builtins()))
+}
+
+/// Make `entity` available to rooms in this process. Embedders call this before creating or
+/// entering rooms of the new kind.
+pub fn register(entity: Arc<dyn Entity>) {
+    registry().write().unwrap_or_else(|e| e.into_inner()).register(entity);
+}
+
+/// Look up a registered entity by name.
+pub fn resolve(name: &str) -> Option<Arc<dyn Entity>> {
+    registry().read().unwrap_or_else(|e| e.into_inner()).get(name)
+}
+
+/// Names of every registered entity.
+pub fn registered() -> Vec<String> {
+    registry().read().unwrap_or_else(|e| e.into_inner()).names()
+}
+
+/// The kv/counter entity rooms have always run.
+pub struct BuiltinEntity;
+
+impl BuiltinEntity {
+    pub const VERSION: &'static str = "2.1.0";
+}
+
+impl Entity for BuiltinEntity {
+    fn name(&self) -> &str {
+        DEFAULT_ENTITY
+    }
+
+    fn version(&self) -> &str {
+        Self::VERSION
+    }
+
+    fn restart(&self, room: &mut Room, _now: i64) {
+        room.entity_state.pending = None;
+    }
+
+    fn handle_input(&self, room: &mut Room, raw: &str, now: i64) -> Option<String> {
+        let mut lines = Vec::new();
+        if let Err(e) = self.stream_input(room, raw, now, &mut lines) {
+            lines.push(e.to_string());
+        }
+        (!lines.is_empty()).then(|| lines.join("\n"))
+    }
+
+    fn stream_input(&self, room: &mut Room, raw: &str, now: i64, out: &mut dyn OutputSink) -> Result<(), RoomError> {
+        if let Some(text) = self.respond(room, raw, now, out) {
+            for line in text.lines() {
+                out.emit(line);
+            }
+        }
+        Ok(())
     }
 }
 
-pub struct Entity;
+impl BuiltinEntity {
+    /// Run one input. Streaming commands write to `out` themselves; everything else returns
+    /// its response.
+    fn respond(&self, room: &mut Room, raw: &str, now: i64, out: &mut dyn OutputSink) -> Option<String> {
+        // Hard refusal pattern, before anything is recorded. Transports already refuse
+        // oversized lines; this covers callers that skip preprocessing.
+        if room.entity_state.pending.is_none() && raw.trim().len() > room.config.max_input_size as usize {
+            return Some(input_size_exceeded(room, now));
+        }
 
-impl Entity {
-    pub fn handle_input(room: &mut Room, raw: &str, now: i64) -> Option<String> {
         // Record input
         room.memory.append(MemoryEntry {
+            seq: 0,
             timestamp: now,
             kind: EntryType::INPUT,
             content: raw.to_string(),
             metadata: serde_json::json!({}),
+            pinned: false,
         });
 
-        // Minimal, deterministic protocol: supports a few stateful operations.
-        // Everything else is acknowledged or ignored depending on content.
-        let trimmed = raw.trim();
+        // Lines of an open block are collected until its terminator, then run as one input.
+        let input = match room.entity_state.pending.take() {
+            Some(mut block) if raw.trim() != block.terminator => {
+                block.lines.push(raw.to_string());
+                if block.size() > room.config.max_input_size as usize {
+                    return Some(input_size_exceeded(room, now));
+                }
+                room.entity_state.pending = Some(block);
+                return None;
+            }
+            Some(block) => format!("{}\n{}", block.head, block.lines.join("\n")),
+            None => {
+                if let Some((head, terminator)) = command::heredoc(raw) {
+                    room.entity_state.pending = Some(PendingBlock { head: head.to_string(), terminator: terminator.to_string(), lines: Vec::new() });
+                    return None;
+                }
+                raw.to_string()
+            }
+        };
+
+        // Deterministic protocol: the grammar in `command`. Anything that is not a command
+        // goes to the room's response rules; when none matches, the entity stays silent.
+        let trimmed = input.trim();
 
         if trimmed.is_empty() {
             return None;
         }
 
-        // Hard refusal pattern
-        if trimmed.len() > room.config.max_input_size as usize {
-            room.memory.append(MemoryEntry {
-                timestamp: now,
-                kind: EntryType::ERROR,
-                content: "ERROR: INPUT_SIZE_EXCEEDED".to_string(),
-                metadata: serde_json::json!({"max": room.config.max_input_size}),
-            });
-            return Some("ERROR: INPUT_SIZE_EXCEEDED".to_string());
-        }
-
-        // Commands (entity-level, not process parsing)
-        // remember key: value
-        if let Some(rest) = trimmed.strip_prefix("remember ") {
-            if let Some((k, v)) = rest.split_once(':') {
-                let key = k.trim().to_string();
-                let val = v.trim().to_string();
-                room.entity_state.kv.insert(key, val);
-                return Some("ENTITY: Stored.".to_string());
+        let cmd = match command::parse(trimmed) {
+            Ok(Some(Command::WhatIs { key })) if !room.entity_state.kv.contains_key(&key) => None,
+            Ok(cmd) => cmd,
+            Err(e) => {
+                room.memory.append(MemoryEntry {
+                    seq: 0,
+                    timestamp: now,
+                    kind: EntryType::ERROR,
+                    content: e.to_string(),
+                    metadata: serde_json::json!({"verb": e.verb, "reason": e.reason}),
+                    pinned: false,
+                });
+                return Some(e.to_string());
             }
-        }
+        };
+        let Some(cmd) = cmd else {
+            let state = &room.entity_state;
+            let template = rules::find(&state.rules, trimmed)?.response.as_deref()?;
+            return Some(entity_lines(rules::render(template, state, trimmed).lines()));
+        };
 
-        // recall key
-        if let Some(rest) = trimmed.strip_prefix("recall ") {
-            let key = rest.trim();
-            if let Some(v) = room.entity_state.kv.get(key) {
-                return Some(format!("ENTITY: {}", v));
+        Some(match cmd {
+            Command::Remember { key, value } => {
+                let old = room.entity_state.kv.insert(key.clone(), value.clone());
+                record_mutation(room, now, "kv_set", &key, serde_json::json!(old), serde_json::json!(value));
+                "ENTITY: Stored.".to_string()
             }
-            return Some("ENTITY: No such entry.".to_string());
-        }
-
-        // counter operations
-        if let Some(rest) = trimmed.strip_prefix("initialize counter ") {
-            let name = rest.trim().to_string();
-            room.entity_state.counters.insert(name, 0);
-            return Some("ENTITY: Counter initialized.".to_string());
-        }
-
-        if let Some(rest) = trimmed.strip_prefix("increment counter ") {
-            let name = rest.trim().to_string();
-            let c = room.entity_state.counters.entry(name.clone()).or_insert(0);
-            *c += 1;
-            return Some(format!("ENTITY: Counter: {}", *c));
-        }
-
-        if let Some(rest) = trimmed.strip_prefix("reset counter ") {
-            let name = rest.trim().to_string();
-            room.entity_state.counters.insert(name, 0);
-            return Some("ENTITY: Counter reset to 0.".to_string());
-        }
-
-        if trimmed == "system status" || trimmed == "status" || trimmed == "system check" {
-            return Some(format!(
+            Command::Recall { key } | Command::WhatIs { key } => match room.entity_state.kv.get(&key) {
+                Some(v) => entity_lines(v.lines()),
+                None => "ENTITY: No such entry.".to_string(),
+            },
+            Command::Forget { key } => match room.entity_state.kv.remove(&key) {
+                Some(old) => {
+                    record_mutation(room, now, "kv_delete", &key, serde_json::json!(old), serde_json::Value::Null);
+                    "ENTITY: Forgotten.".to_string()
+                }
+                None => "ENTITY: No such entry.".to_string(),
+            },
+            Command::Keys { prefix } => {
+                let mut keys: Vec<&String> = room.entity_state.kv.keys()
+                    .filter(|k| prefix.as_deref().is_none_or(|p| k.starts_with(p)))
+                    .collect();
+                keys.sort();
+                if keys.is_empty() {
+                    "ENTITY: No entries.".to_string()
+                } else {
+                    entity_lines(keys)
+                }
+            }
+            Command::Dump => {
+                let sorted: BTreeMap<&String, &String> = room.entity_state.kv.iter().collect();
+                format!("ENTITY: {}", serde_json::to_string(&sorted).unwrap_or_default())
+            }
+            Command::Rename { from, to } => {
+                if room.entity_state.kv.contains_key(&to) {
+                    format!("ENTITY: Key {} already exists.", to)
+                } else if let Some(value) = room.entity_state.kv.remove(&from) {
+                    record_mutation(room, now, "kv_delete", &from, serde_json::json!(value), serde_json::Value::Null);
+                    record_mutation(room, now, "kv_set", &to, serde_json::Value::Null, serde_json::json!(value));
+                    room.entity_state.kv.insert(to, value);
+                    "ENTITY: Renamed.".to_string()
+                } else {
+                    "ENTITY: No such entry.".to_string()
+                }
+            }
+            Command::ImportKv { entries } => {
+                let n = entries.len();
+                for (key, value) in entries {
+                    let old = room.entity_state.kv.insert(key.clone(), value.clone());
+                    record_mutation(room, now, "kv_set", &key, serde_json::json!(old), serde_json::json!(value));
+                }
+                format!("ENTITY: Imported {} entries.", n)
+            }
+            Command::CounterInit { name, value } => {
+                write_counter(room, now, "counter_init", &name, value);
+                "ENTITY: Counter initialized.".to_string()
+            }
+            Command::CounterIncrement { name } => add_to_counter(room, now, "counter_increment", &name, 1),
+            Command::CounterDecrement { name } => add_to_counter(room, now, "counter_decrement", &name, -1),
+            Command::CounterAdd { name, delta } => add_to_counter(room, now, "counter_add", &name, delta),
+            Command::CounterSet { name, value } => {
+                write_counter(room, now, "counter_set", &name, value);
+                format!("ENTITY: Counter: {}", value)
+            }
+            Command::CounterReset { name } => {
+                write_counter(room, now, "counter_reset", &name, 0);
+                "ENTITY: Counter reset to 0.".to_string()
+            }
+            Command::CounterValue { name } => match room.entity_state.counters.get(&name) {
+                Some(v) => format!("ENTITY: Counter: {}", v),
+                None => "ENTITY: No such counter.".to_string(),
+            },
+            Command::CounterDelete { name } => match room.entity_state.counters.remove(&name) {
+                Some(old) => {
+                    room.entity_state.counter_history.remove(&name);
+                    record_mutation(room, now, "counter_delete", &name, serde_json::json!(old), serde_json::Value::Null);
+                    "ENTITY: Counter deleted.".to_string()
+                }
+                None => "ENTITY: No such counter.".to_string(),
+            },
+            Command::Counters => {
+                let sorted: BTreeMap<&String, &i64> = room.entity_state.counters.iter().collect();
+                if sorted.is_empty() {
+                    "ENTITY: No counters.".to_string()
+                } else {
+                    entity_lines(sorted.iter().map(|(k, v)| format!("{}: {}", k, v)))
+                }
+            }
+            // pin/unpin <timestamp>: protect the memory entries recorded then from eviction
+            Command::Pin { timestamp } => set_pinned(room, timestamp, true),
+            Command::Unpin { timestamp } => set_pinned(room, timestamp, false),
+            Command::Status => format!(
                 "ENTITY: Operational. Memory usage {}%. State: {:?}.",
                 room.memory_utilization_percent(),
                 room.state
-            ));
-        }
+            ),
+            Command::Report => {
+                report(room, out);
+                return None;
+            }
+            Command::RuleAdd { priority, kind, pattern, response } => {
+                let id = room.entity_state.rules.iter().map(|r| r.id).max().unwrap_or(0) + 1;
+                let rule = ResponseRule::new(id, priority, kind, pattern, response);
+                record_mutation(room, now, "rule_add", &id.to_string(), serde_json::Value::Null, serde_json::json!(rule));
+                room.entity_state.rules.push(rule);
+                format!("ENTITY: Rule {} added.", id)
+            }
+            Command::Rules => {
+                if room.entity_state.rules.is_empty() {
+                    "ENTITY: No rules.".to_string()
+                } else {
+                    entity_lines(rules::ordered(&room.entity_state.rules))
+                }
+            }
+            Command::RuleRemove { id } => match room.entity_state.rules.iter().position(|r| r.id == id) {
+                Some(i) => {
+                    let old = room.entity_state.rules.remove(i);
+                    record_mutation(room, now, "rule_remove", &id.to_string(), serde_json::json!(old), serde_json::Value::Null);
+                    "ENTITY: Rule removed.".to_string()
+                }
+                None => "ENTITY: No such rule.".to_string(),
+            },
+            Command::Help { topic } => entity_lines(command::help(topic.as_deref())),
+        })
+    }
+}
 
-        // Silence heuristic: ignore obvious noise
-        if trimmed.len() < 6 && trimmed.chars().all(|c| c.is_ascii_punctuation() || c.is_ascii_digit()) {
-            return None;
+/// Add `delta` to a counter, creating it at 0, and emit the new value. Overflow leaves the
+/// counter unchanged.
+fn add_to_counter(room: &mut Room, now: i64, op: &str, name: &str, delta: i64) -> String {
+    let current = room.entity_state.counters.get(name).copied().unwrap_or(0);
+    let Some(new) = current.checked_add(delta) else {
+        let err = RoomError::CounterOverflow { name: name.to_string() }.to_string();
+        room.memory.append(MemoryEntry {
+            seq: 0,
+            timestamp: now,
+            kind: EntryType::ERROR,
+            content: err.clone(),
+            metadata: serde_json::json!({"counter": name, "value": current, "delta": delta}),
+            pinned: false,
+        });
+        return err;
+    };
+    write_counter(room, now, op, name, new);
+    format!("ENTITY: Counter: {}", new)
+}
+
+/// Store a counter value, log the mutation and sample it into the counter's history when
+/// the room keeps one.
+pub(crate) fn write_counter(room: &mut Room, now: i64, op: &str, name: &str, value: i64) {
+    let old = room.entity_state.counters.insert(name.to_string(), value);
+    record_mutation(room, now, op, name, serde_json::json!(old), serde_json::json!(value));
+    let keep = room.config.counter_history;
+    if keep > 0 {
+        let series = room.entity_state.counter_history.entry(name.to_string()).or_default();
+        series.push_back(CounterSample { timestamp: now, value });
+        while series.len() > keep {
+            series.pop_front();
         }
+    }
+}
 
-        Some("ENTITY: Acknowledged.".to_string())
+/// Stream the room report, one line per fact.
+fn report(room: &Room, out: &mut dyn OutputSink) {
+    let m = &room.metadata;
+    let usage = if room.memory.capacity == 0 { 0.0 } else { room.memory.usage as f64 * 100.0 / room.memory.capacity as f64 };
+    out.emit("ENTITY: Beginning report generation.");
+    out.emit("ENTITY: Section 1: System Overview");
+    out.emit(&format!("ENTITY: Current state is {:?}. Memory usage {:.1}%.", room.state, usage));
+    out.emit(&format!("ENTITY: Entries held: {}. Compressed blocks: {}.", room.memory.len(), room.memory.blocks.len()));
+    out.emit("ENTITY: Section 2: Historical Analysis");
+    out.emit(&format!("ENTITY: Total inputs processed: {}", m.total_inputs));
+    out.emit(&format!("ENTITY: Outputs: {}. Errors: {}. Timeouts: {}.", m.total_outputs, m.total_errors, m.total_timeouts));
+    out.emit(&format!("ENTITY: Keys stored: {}. Counters: {}.", room.entity_state.kv.len(), room.entity_state.counters.len()));
+    out.emit("ENTITY: Section 3: Recommendations");
+    let mut advice = Vec::new();
+    if usage >= 80.0 {
+        advice.push("Memory usage high. Raise the memory limit or add retention rules.".to_string());
+    }
+    if room.memory.pinned_usage() * 5 >= room.memory.pinned_capacity * 4 {
+        advice.push("Pinned memory near its cap. Unpin entries no longer needed.".to_string());
+    }
+    if let Some(e) = &m.last_error {
+        advice.push(format!("Last error: {}", e));
     }
+    if advice.is_empty() {
+        out.emit("ENTITY: No immediate actions required.");
+    }
+    for a in advice {
+        out.send(Output::new(OutputLevel::WARN, format!("ENTITY: {}", a)));
+    }
+    out.send(Output::new(OutputLevel::INFO, "ENTITY: Report complete.").with_payload(serde_json::json!({
+        "state": room.state,
+        "memory_usage_percent": usage,
+        "total_inputs": m.total_inputs,
+        "total_outputs": m.total_outputs,
+        "total_errors": m.total_errors,
+        "total_timeouts": m.total_timeouts,
+    })));
+}
+
+/// One `ENTITY:` line per item.
+fn entity_lines<T: std::fmt::Display>(items: impl IntoIterator<Item = T>) -> String {
+    items.into_iter().map(|l| format!("ENTITY: {}", l)).collect::<Vec<_>>().join("\n")
+}
+
+fn set_pinned(room: &mut Room, ts: i64, pinned: bool) -> String {
+    match room.memory.set_pinned(ts, pinned) {
+        Ok(0) => format!("ENTITY: No entries at {}.", ts),
+        Ok(n) => format!("ENTITY: {} {} entries.", if pinned { "Pinned" } else { "Unpinned" }, n),
+        Err(e) => e.to_string(),
+    }
+}
+
+fn input_size_exceeded(room: &mut Room, now: i64) -> String {
+    let err = RoomError::InputSizeExceeded { max: room.config.max_input_size }.to_string();
+    room.memory.append(MemoryEntry {
+        seq: 0,
+        timestamp: now,
+        kind: EntryType::ERROR,
+        content: err.clone(),
+        metadata: serde_json::json!({"max": room.config.max_input_size}),
+        pinned: false,
+    });
+    err
+}
+
+/// Log a kv, counter or rule mutation as a STATE_CHANGE entry; `old` is null when the key is new.
+pub(crate) fn record_mutation(room: &mut Room, now: i64, op: &str, key: &str, old: serde_json::Value, new: serde_json::Value) {
+    room.memory.append(MemoryEntry {
+        seq: 0,
+        timestamp: now,
+        kind: EntryType::STATE_CHANGE,
+        content: format!("STATE_CHANGE: {} {}", op.to_uppercase(), key),
+        metadata: serde_json::json!({"op": op, "key": key, "old": old, "new": new}),
+        pinned: false,
+    });
 }
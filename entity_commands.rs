@@ -0,0 +1,263 @@
+mod common;
+
+use backrooms_terminal::command::{parse, Command, DEFAULT_COUNTER, GRAMMAR};
+use backrooms_terminal::config::Config;
+use backrooms_terminal::entity::{counter_rate, parse_output_level, Output, OutputLevel, OutputSink};
+use backrooms_terminal::memory::EntryType;
+use backrooms_terminal::session::{process_input, stream_input};
+
+#[test]
+fn parses_grammar_and_aliases() {
+    let counter = |name: &str| name.to_string();
+    let cases = vec![
+        ("remember reactor 3: venting", Command::Remember { key: "reactor 3".to_string(), value: "venting".to_string() }),
+        ("Store a:b", Command::Remember { key: "a".to_string(), value: "b".to_string() }),
+        ("retrieve reactor 3", Command::Recall { key: "reactor 3".to_string() }),
+        ("what is color", Command::WhatIs { key: "color".to_string() }),
+        ("initialize counter at 5", Command::CounterInit { name: counter(DEFAULT_COUNTER), value: 5 }),
+        ("init counter visits at -2", Command::CounterInit { name: counter("visits"), value: -2 }),
+        ("initialize counter visits", Command::CounterInit { name: counter("visits"), value: 0 }),
+        ("incr counter", Command::CounterIncrement { name: counter(DEFAULT_COUNTER) }),
+        ("reset counter visits", Command::CounterReset { name: counter("visits") }),
+        ("what is counter value", Command::CounterValue { name: counter(DEFAULT_COUNTER) }),
+        ("what is counter visits value", Command::CounterValue { name: counter("visits") }),
+        ("pin 42", Command::Pin { timestamp: 42 }),
+        ("request status", Command::Status),
+        ("? pin", Command::Help { topic: Some("pin".to_string()) }),
+    ];
+    for (input, expected) in cases {
+        assert_eq!(parse(input).unwrap(), Some(expected), "{}", input);
+    }
+
+    assert_eq!(parse("system diagnostic").unwrap(), None);
+    assert_eq!(parse("describe current state").unwrap(), None);
+
+    for free_text in ["remember key", "increment visits", "unpin soon", "show me the door", "set the table", "add milk to the list", "rule the world"] {
+        assert_eq!(parse(free_text).unwrap(), None, "{}", free_text);
+    }
+
+    let err = parse("remember").unwrap_err();
+    assert_eq!(err.to_string(), "ERROR: PARSE_ERROR (expected ':' between key and value; usage: remember <key>: <value>)");
+    assert_eq!(parse("remember : value").unwrap_err().reason, "missing key");
+    assert_eq!(parse("initialize counter x at").unwrap_err().verb, "initialize");
+    assert_eq!(parse("increment").unwrap_err().reason, "expected 'counter'");
+    assert_eq!(parse("add many to counter").unwrap_err().reason, "expected an integer, got 'many'");
+    assert_eq!(parse("unpin").unwrap_err().verb, "unpin");
+}
+
+#[test]
+fn entity_runs_parsed_commands_and_documents_them() {
+    let cfg = Config::load(None).unwrap();
+    let mut room = common::room_from("c", &cfg);
+    let mut run = common::runner(&mut room, &cfg);
+
+    assert_eq!(run.at("initialize counter at 0", 101), "ENTITY: Counter initialized.");
+    assert_eq!(run.at("increment counter", 102), "ENTITY: Counter: 1");
+    assert_eq!(run.at("increment counter", 103), "ENTITY: Counter: 2");
+    assert_eq!(run.at("what is counter value", 104), "ENTITY: Counter: 2");
+    assert_eq!(run.at("reset counter", 105), "ENTITY: Counter reset to 0.");
+    assert!(run.at("remember : value", 106).starts_with("ERROR: PARSE_ERROR"));
+    assert_eq!(run.at("recall key", 107), "ENTITY: No such entry.");
+
+    let help = run.at("help", 108);
+    assert_eq!(help.lines().count(), GRAMMAR.len());
+    assert!(help.lines().all(|l| l.starts_with("ENTITY: ")));
+    assert!(help.contains("remember <key>: <value>"));
+
+    assert_eq!(room.entity_state.counters[DEFAULT_COUNTER], 0);
+    assert_eq!(room.memory.count_of(EntryType::ERROR), 1);
+}
+
+#[test]
+fn kv_commands_are_deterministic() {
+    let cfg = Config::load(None).unwrap();
+    let mut room = common::room_from("kv", &cfg);
+    let mut run = common::runner(&mut room, &cfg);
+
+    run.input("remember zeta: 1");
+    run.input("remember alpha: 2");
+    run.input("remember alpine: 3");
+    assert_eq!(run.input("keys").unwrap(), "ENTITY: alpha\nENTITY: alpine\nENTITY: zeta");
+    assert_eq!(run.input("keys alp").unwrap(), "ENTITY: alpha\nENTITY: alpine");
+    assert_eq!(run.input("keys q").unwrap(), "ENTITY: No entries.");
+
+    assert_eq!(run.input("rename zeta alpha").unwrap(), "ENTITY: Key alpha already exists.");
+    assert_eq!(run.input("rename zeta to omega").unwrap(), "ENTITY: Renamed.");
+    assert_eq!(run.input("forget alpine").unwrap(), "ENTITY: Forgotten.");
+    assert_eq!(run.input("forget alpine").unwrap(), "ENTITY: No such entry.");
+    assert_eq!(run.input("dump").unwrap(), r#"ENTITY: {"alpha":"2","omega":"1"}"#);
+
+    // Multi-line values are read up to the terminator and recalled line by line.
+    assert_eq!(run.input("remember notes: <<END"), None);
+    assert_eq!(run.input("first line"), None);
+    assert_eq!(run.input(""), None);
+    assert_eq!(run.input("third line"), None);
+    assert_eq!(run.input("END").unwrap(), "ENTITY: Stored.");
+    assert_eq!(run.input("recall notes").unwrap(), "ENTITY: first line\nENTITY: \nENTITY: third line");
+
+    assert_eq!(run.input(r#"import kv {"beta": "x", "n": 7, "flag": true}"#).unwrap(), "ENTITY: Imported 3 entries.");
+    assert_eq!(run.input("import kv <<JSON"), None);
+    assert_eq!(run.input(r#"{"gamma": "y","#), None);
+    assert_eq!(run.input(r#" "alpha": "overwritten"}"#), None);
+    assert_eq!(run.input("JSON").unwrap(), "ENTITY: Imported 2 entries.");
+    assert!(run.input(r#"import kv {"nested": {}}"#).unwrap().starts_with("ERROR: PARSE_ERROR (value of 'nested' is not a scalar"));
+
+    let dump = run.input("dump").unwrap();
+    assert_eq!(
+        dump,
+        r#"ENTITY: {"alpha":"overwritten","beta":"x","flag":"true","gamma":"y","n":"7","notes":"first line\n\nthird line","omega":"1"}"#
+    );
+    // A dump imports back unchanged.
+    let before = room.entity_state.kv.clone();
+    process_input(&mut room, &format!("import kv {}", dump.trim_start_matches("ENTITY: ")), 102, &cfg);
+    assert_eq!(room.entity_state.kv, before);
+    assert!(room.entity_state.pending.is_none());
+}
+
+#[test]
+fn counter_operations_check_overflow_and_keep_history() {
+    let mut cfg = Config::load(None).unwrap();
+    cfg.entity.counter_history = 3;
+    let mut room = common::room_from("n", &cfg);
+    let mut run = common::runner(&mut room, &cfg);
+
+    assert_eq!(run.at("add 5 to counter hits", 100), "ENTITY: Counter: 5");
+    assert_eq!(run.at("subtract 2 from counter hits", 102), "ENTITY: Counter: 3");
+    assert_eq!(run.at("decrement counter hits", 104), "ENTITY: Counter: 2");
+    assert_eq!(run.at("increment counter hits", 106), "ENTITY: Counter: 3");
+    assert_eq!(run.at("get counter hits", 107), "ENTITY: Counter: 3");
+    assert_eq!(run.at("set counter big to 9223372036854775807", 108), "ENTITY: Counter: 9223372036854775807");
+    assert_eq!(run.at("increment counter big", 109), "ERROR: COUNTER_OVERFLOW (big)");
+    assert!(run.at("subtract lots from counter hits", 110).starts_with("ERROR: PARSE_ERROR (expected an integer, got 'lots'"));
+    assert!(run.at("set counter hits to many", 110).starts_with("ERROR: PARSE_ERROR (expected an integer, got 'many'"));
+    assert_eq!(run.at("list counters", 111), "ENTITY: big: 9223372036854775807\nENTITY: hits: 3");
+    assert_eq!(run.at("delete counter big", 112), "ENTITY: Counter deleted.");
+    assert_eq!(run.at("show counter big", 113), "ENTITY: No such counter.");
+
+    let series = &room.entity_state.counter_history["hits"];
+    let samples: Vec<(i64, i64)> = series.iter().map(|s| (s.timestamp, s.value)).collect();
+    assert_eq!(samples, vec![(102, 3), (104, 2), (106, 3)]);
+    assert_eq!(counter_rate(series), Some(0.0));
+    assert!(!room.entity_state.counter_history.contains_key("big"));
+    assert_eq!(room.entity_state.counters.len(), 1);
+}
+
+#[test]
+fn report_streams_one_output_entry_per_line() {
+    let cfg = Config::load(None).unwrap();
+    let mut room = common::room_from("r", &cfg);
+    process_input(&mut room, "remember a: b", 101, &cfg);
+
+    let report = process_input(&mut room, "generate report", 102, &cfg).unwrap();
+    let lines: Vec<&str> = report.lines().collect();
+    assert_eq!(lines.first(), Some(&"ENTITY: Beginning report generation."));
+    assert_eq!(lines.last(), Some(&"ENTITY: Report complete."));
+    assert!(lines.contains(&"ENTITY: Total inputs processed: 2"));
+    assert!(lines.contains(&"ENTITY: No immediate actions required."));
+
+    let outputs = room.memory.all_entries().unwrap().into_iter().filter(|e| e.kind == EntryType::OUTPUT).count();
+    assert_eq!(outputs, 1 + lines.len());
+    assert_eq!(parse("generate noise").unwrap(), None);
+    assert!(parse("generate").is_err());
+}
+
+struct Collect(Vec<Output>);
+
+impl OutputSink for Collect {
+    fn send(&mut self, output: Output) {
+        self.0.push(output);
+    }
+}
+
+#[test]
+fn output_is_leveled_and_truncated_with_a_marker() {
+    let mut cfg = Config::load(None).unwrap();
+    let mut room = common::room_from("o", &cfg);
+
+    let mut out = Collect(Vec::new());
+    stream_input(&mut room, "remember : value", 101, &cfg, &mut out);
+    assert_eq!(out.0[0].level, OutputLevel::ERROR);
+
+    let mut out = Collect(Vec::new());
+    stream_input(&mut room, "report", 102, &cfg, &mut out);
+    let last = out.0.last().unwrap();
+    assert!(out.0.iter().any(|o| o.level == OutputLevel::WARN && o.text.contains("Last error: ERROR: PARSE_ERROR")));
+    assert_eq!(last.payload.as_ref().unwrap()["total_errors"], 1);
+    let recorded = room.memory.entries.iter().rev().find(|e| e.kind == EntryType::OUTPUT).unwrap();
+    assert_eq!(recorded.metadata["level"], "INFO");
+    assert_eq!(recorded.metadata["payload"]["total_inputs"], 2);
+
+    cfg.limits.max_output_size = 60;
+    let mut out = Collect(Vec::new());
+    stream_input(&mut room, "generate report", 103, &cfg, &mut out);
+    let texts: Vec<&str> = out.0.iter().map(|o| o.text.as_str()).collect();
+    assert_eq!(texts[0], "ENTITY: Beginning report generation.");
+    assert_eq!(texts[1], "ENTITY: Section 1: Syste");
+    assert_eq!(texts[0].len() + texts[1].len(), 60);
+    assert!(texts[2].starts_with("[OUTPUT TRUNCATED: "));
+    assert!(texts[2].ends_with(" bytes over the 60 byte limit]"));
+    assert_eq!(out.0[2].level, OutputLevel::WARN);
+    assert_eq!(texts.len(), 3);
+    assert!(room.memory.entries.back().unwrap().content.starts_with("[OUTPUT TRUNCATED"));
+    assert_eq!(parse_output_level("warn").unwrap(), OutputLevel::WARN);
+}
+
+#[test]
+fn response_rules_replace_the_acknowledgement() {
+    let cfg = Config::load(None).unwrap();
+    let mut room = common::room_from("rules", &cfg);
+    let mut run = common::runner(&mut room, &cfg);
+
+    assert_eq!(run.input("hello there").unwrap(), "ENTITY: Acknowledged.");
+    assert_eq!(run.input("42!"), None);
+
+    run.input("remember name: Ada");
+    run.input("increment counter visits");
+    assert_eq!(run.input("rule add glob hello* => Hi {kv:name}, visit {counter:visits}. {unknown}").unwrap(), "ENTITY: Rule 3 added.");
+    assert_eq!(run.input("rule add priority 5 exact hello there => silence").unwrap(), "ENTITY: Rule 4 added.");
+    assert_eq!(run.input("rule add regex (?i)^echo (.+)$ => {input}").unwrap(), "ENTITY: Rule 5 added.");
+    assert!(run.input("rule add regex ([ => x").unwrap().starts_with("ERROR: PARSE_ERROR (invalid regex '(['"));
+    assert!(run.input("rule add fuzzy x => y").unwrap().starts_with("ERROR: PARSE_ERROR (expected 'exact', 'glob' or 'regex'"));
+
+    assert_eq!(run.input("hello there"), None);
+    assert_eq!(run.input("hello world").unwrap(), "ENTITY: Hi Ada, visit 1. {unknown}");
+    assert_eq!(run.input("ECHO ping").unwrap(), "ENTITY: ECHO ping");
+    assert_eq!(
+        run.input("rule list").unwrap(),
+        [
+            "ENTITY: #4 priority 5 exact hello there => silence",
+            "ENTITY: #3 priority 0 glob hello* => Hi {kv:name}, visit {counter:visits}. {unknown}",
+            "ENTITY: #5 priority 0 regex (?i)^echo (.+)$ => {input}",
+            "ENTITY: #1 priority -100 regex ^[[:punct:][:digit:]]{1,5}$ => silence",
+            "ENTITY: #2 priority -200 glob * => Acknowledged.",
+        ]
+        .join("\n")
+    );
+
+    assert_eq!(run.input("rule remove 2").unwrap(), "ENTITY: Rule removed.");
+    assert_eq!(run.input("rule remove 2").unwrap(), "ENTITY: No such rule.");
+    assert_eq!(run.input("goodbye"), None);
+    assert_eq!(room.entity_state.rules.len(), 4);
+
+    let added = room.memory.all_entries().unwrap().into_iter()
+        .filter(|e| e.kind == EntryType::STATE_CHANGE && e.metadata["op"] == "rule_add")
+        .count();
+    assert_eq!(added, 3);
+}
+
+#[test]
+fn free_text_starting_with_a_verb_reaches_the_rules() {
+    let cfg = Config::load(None).unwrap();
+    let mut room = common::room_from("verbs", &cfg);
+    let mut run = common::runner(&mut room, &cfg);
+
+    assert_eq!(run.input("rule add exact show me the door => The door is behind you.").unwrap(), "ENTITY: Rule 3 added.");
+    assert_eq!(run.input("rule add glob what is * => Nobody knows.").unwrap(), "ENTITY: Rule 4 added.");
+    assert_eq!(run.input("show me the door").unwrap(), "ENTITY: The door is behind you.");
+    assert_eq!(run.input("set the table").unwrap(), "ENTITY: Acknowledged.");
+    assert_eq!(run.input("what is this place").unwrap(), "ENTITY: Nobody knows.");
+    run.input("remember this place: the backrooms");
+    assert_eq!(run.input("what is this place").unwrap(), "ENTITY: the backrooms");
+    assert_eq!(run.input("show counter").unwrap(), "ENTITY: No such counter.");
+    assert_eq!(room.memory.count_of(EntryType::ERROR), 0);
+}
@@ -0,0 +1,323 @@
+mod common;
+
+use backrooms_terminal::config::{Config, ProcessEntityConfig};
+use backrooms_terminal::entity::{self, Entity, Output, OutputSink};
+use backrooms_terminal::error::RoomError;
+use backrooms_terminal::history;
+use backrooms_terminal::memory::{EntryType, MemoryEntry};
+use backrooms_terminal::process;
+use backrooms_terminal::room::{Room, RoomState};
+use backrooms_terminal::session::{process_input, room_config_from, stream_input};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+struct Echo;
+
+impl Entity for Echo {
+    fn name(&self) -> &str {
+        "echo"
+    }
+
+    fn version(&self) -> &str {
+        "0.1.0"
+    }
+
+    fn init(&self, room: &mut Room, _now: i64) {
+        room.entity_state.version = self.version().to_string();
+        room.entity_state.custom = serde_json::json!({"transitions": 0});
+    }
+
+    fn handle_input(&self, room: &mut Room, input: &str, now: i64) -> Option<String> {
+        room.memory.append(MemoryEntry{
+            seq: 0,
+            timestamp: now,
+            kind: EntryType::INPUT,
+            content: input.to_string(),
+            metadata: serde_json::json!({}),
+            pinned: false,
+        });
+        Some(format!("ECHO: {}", input))
+    }
+
+    fn on_state_change(&self, room: &mut Room, _from: RoomState, _to: RoomState, _now: i64) {
+        let n = room.entity_state.custom["transitions"].as_i64().unwrap_or(0);
+        room.entity_state.custom["transitions"] = serde_json::json!(n + 1);
+    }
+}
+
+#[test]
+fn rooms_run_the_entity_they_were_created_with() {
+    entity::register(Arc::new(Echo));
+    assert!(entity::registered().contains(&"echo".to_string()));
+
+    let cfg = Config::load(None).unwrap();
+    let mut rc = room_config_from(&cfg);
+    rc.entity = "echo".to_string();
+    let mut room = common::room("e", rc, 100);
+    assert_eq!(room.entity_state.version, "0.1.0");
+
+    assert_eq!(process_input(&mut room, "remember a: b", 101, &cfg).as_deref(), Some("ECHO: remember a: b"));
+    assert!(room.entity_state.kv.is_empty());
+    room.transition(RoomState::IDLE, "exit", 102);
+    assert_eq!(room.entity_state.custom["transitions"], 1);
+
+    // The kind is persisted with the room.
+    let restored: Room = serde_json::from_str(&serde_json::to_string(&room).unwrap()).unwrap();
+    assert_eq!(restored.config.entity, "echo");
+}
+
+#[test]
+fn unknown_entity_is_reported() {
+    let cfg = Config::load(None).unwrap();
+    let mut rc = room_config_from(&cfg);
+    rc.entity = "missing".to_string();
+    let mut room = common::room("m", rc, 100);
+    assert_eq!(process_input(&mut room, "hello there", 101, &cfg).as_deref(), Some("ERROR: UNKNOWN_ENTITY (missing)"));
+}
+
+/// Sleeps on "stall" until aborted or three seconds have passed.
+struct Sleepy {
+    aborted: AtomicBool,
+}
+
+impl Entity for Sleepy {
+    fn name(&self) -> &str {
+        "sleepy"
+    }
+
+    fn version(&self) -> &str {
+        "0.1.0"
+    }
+
+    fn handle_input(&self, room: &mut Room, input: &str, now: i64) -> Option<String> {
+        room.entity_state.kv.insert("seen".to_string(), input.to_string());
+        room.memory.append(MemoryEntry{
+            seq: 0,
+            timestamp: now,
+            kind: EntryType::OBSERVATION,
+            content: input.to_string(),
+            metadata: serde_json::json!({}),
+            pinned: false,
+        });
+        if input == "stall" {
+            let started = Instant::now();
+            while !self.aborted.load(Ordering::SeqCst) && started.elapsed() < Duration::from_secs(3) {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        }
+        Some("SLEPT".to_string())
+    }
+
+    fn abort(&self, _room_id: &str) {
+        self.aborted.store(true, Ordering::SeqCst);
+    }
+
+    fn restart(&self, room: &mut Room, _now: i64) {
+        room.entity_state.custom = serde_json::json!({"restarted": true});
+    }
+}
+
+#[test]
+fn slow_entity_is_aborted_and_its_input_rolled_back() {
+    let sleepy = Arc::new(Sleepy { aborted: AtomicBool::new(false) });
+    entity::register(sleepy.clone());
+    let cfg = Config::load(None).unwrap();
+    let mut rc = room_config_from(&cfg);
+    rc.entity = "sleepy".to_string();
+    rc.timeout_seconds = 1;
+    let mut room = common::room("s", rc, 100);
+
+    assert_eq!(process_input(&mut room, "quick", 101, &cfg).as_deref(), Some("SLEPT"));
+    let usage = room.memory.usage;
+    let started = Instant::now();
+    let out = process_input(&mut room, "stall", 102, &cfg).unwrap();
+    assert_eq!(out, "ERROR: ENTITY_TIMEOUT (1 seconds)\nOPERATION ABORTED\nENTITY RESTARTED\nSTATE: ACTIVE (recovered)");
+    assert!(sleepy.aborted.load(Ordering::SeqCst));
+    assert!(started.elapsed() < Duration::from_secs(3));
+
+    let observations: Vec<String> = room.memory.entries.iter().filter(|e| e.kind == EntryType::OBSERVATION).map(|e| e.content.clone()).collect();
+    assert_eq!(observations, vec!["quick"]);
+    assert!(room.memory.usage > usage);
+    assert_eq!(room.memory.usage, room.memory.computed_usage());
+
+    assert_eq!(room.entity_state.kv["seen"], "quick");
+    assert_eq!(room.entity_state.custom["restarted"], true);
+    assert_eq!(room.metadata.total_timeouts, 1);
+    assert_eq!(room.metadata.total_errors, 1);
+    assert_eq!(room.memory.count_of(EntryType::ERROR), 1);
+    assert_eq!(process_input(&mut room, "again", 103, &cfg).as_deref(), Some("SLEPT"));
+}
+
+struct Crashy;
+
+impl Entity for Crashy {
+    fn name(&self) -> &str {
+        "crashy"
+    }
+
+    fn version(&self) -> &str {
+        "0.1.0"
+    }
+
+    fn handle_input(&self, room: &mut Room, input: &str, now: i64) -> Option<String> {
+        room.entity_state.kv.insert("seen".to_string(), input.to_string());
+        room.memory.append(MemoryEntry{
+            seq: 0,
+            timestamp: now,
+            kind: EntryType::INPUT,
+            content: input.to_string(),
+            metadata: serde_json::json!({}),
+            pinned: false,
+        });
+        if input == "boom" {
+            panic!("entity fault on {}", input);
+        }
+        Some("OK".to_string())
+    }
+}
+
+#[test]
+fn entity_panic_discards_the_input() {
+    entity::register(Arc::new(Crashy));
+    let cfg = Config::load(None).unwrap();
+    for timeout in [0, 5] {
+        let mut rc = room_config_from(&cfg);
+        rc.entity = "crashy".to_string();
+        rc.timeout_seconds = timeout;
+        let mut room = common::room("c", rc, 100);
+
+        assert_eq!(process_input(&mut room, "fine", 101, &cfg).as_deref(), Some("OK"));
+        let out = process_input(&mut room, "boom", 102, &cfg).unwrap();
+        assert!(out.starts_with("FATAL: ENTITY_CRASHED (entity fault on boom)\nATTEMPTING RECOVERY..."), "{}", out);
+        assert!(out.ends_with("LAST INPUT: [discarded]"));
+
+        assert_eq!(room.entity_state.kv["seen"], "fine");
+        assert_eq!(room.memory.count_of(EntryType::INPUT), 1);
+        assert_eq!(room.memory.count_of(EntryType::ERROR), 1);
+        assert_eq!(room.metadata.total_errors, 1);
+        assert_eq!(room.metadata.last_error.as_deref(), Some("FATAL: ENTITY_CRASHED (entity fault on boom)"));
+        assert_eq!(process_input(&mut room, "after", 103, &cfg).as_deref(), Some("OK"));
+    }
+}
+
+struct Ticker;
+
+impl Entity for Ticker {
+    fn name(&self) -> &str {
+        "ticker"
+    }
+
+    fn version(&self) -> &str {
+        "0.1.0"
+    }
+
+    fn handle_input(&self, _room: &mut Room, _input: &str, _now: i64) -> Option<String> {
+        None
+    }
+
+    fn stream_input(&self, _room: &mut Room, input: &str, _now: i64, out: &mut dyn OutputSink) -> Result<(), RoomError> {
+        out.emit(&format!("TICK: {}", input));
+        std::thread::sleep(std::time::Duration::from_millis(300));
+        out.emit("TOCK");
+        Ok(())
+    }
+}
+
+struct Timed(Vec<(String, Instant)>);
+
+impl OutputSink for Timed {
+    fn send(&mut self, output: Output) {
+        self.0.push((output.text, Instant::now()));
+    }
+}
+
+#[test]
+fn streamed_lines_arrive_before_the_entity_finishes() {
+    entity::register(Arc::new(Ticker));
+    let cfg = Config::load(None).unwrap();
+    let mut rc = room_config_from(&cfg);
+    rc.entity = "ticker".to_string();
+    let mut room = common::room("t", rc, 100);
+
+    let mut sink = Timed(Vec::new());
+    stream_input(&mut room, "go", 101, &cfg, &mut sink);
+    let lines: Vec<&str> = sink.0.iter().map(|(l, _)| l.as_str()).collect();
+    assert_eq!(lines, vec!["TICK: go", "TOCK"]);
+    assert!(sink.0[1].1 - sink.0[0].1 >= std::time::Duration::from_millis(250));
+
+    let outputs: Vec<String> = room.memory.entries.iter().filter(|e| e.kind == EntryType::OUTPUT).map(|e| e.content.clone()).collect();
+    assert_eq!(outputs, vec!["TICK: go", "TOCK"]);
+    assert_eq!(room.metadata.total_outputs, 2);
+    assert_eq!(process_input(&mut room, "again", 102, &cfg).as_deref(), Some("TICK: again\nTOCK"));
+}
+
+#[cfg(unix)]
+const PROCESS_SCRIPT: &str = r#"
+while read -r line; do
+  case "$line" in
+    *'"input":"die"'*) exit 3 ;;
+    *'"input":"hang"'*) sleep 3 ;;
+    *'"input":"fork"'*)
+      sleep 30 </dev/null >/dev/null 2>&1 &
+      printf '{"type":"output","text":"ENTITY: forked %s"}\n' "$!" ;;
+  esac
+  printf '{"type":"output","text":"ENTITY: pid %s fds %s"}\n' "$$" "$(ulimit -n)"
+  printf '{"type":"output","level":"WARN","text":"ENTITY: careful","payload":{"n":1}}\n'
+  printf '{"type":"done","kv":{"last":"seen"},"counters":{"calls":1}}\n'
+done
+"#;
+
+#[cfg(unix)]
+#[test]
+fn process_entity_speaks_json_lines_and_is_restarted() {
+    let mut cfg = Config::load(None).unwrap();
+    cfg.entity.processes.insert("shell".to_string(), ProcessEntityConfig {
+        command: vec!["sh".to_string(), "-c".to_string(), PROCESS_SCRIPT.to_string()],
+        cpu_seconds: 60,
+        address_space: 0,
+        open_files: 32,
+    });
+    process::register_configured(&cfg.entity);
+    let mut rc = room_config_from(&cfg);
+    rc.entity = "shell".to_string();
+    rc.timeout_seconds = 1;
+    let mut room = common::room("p", rc, 100);
+    let pid = |out: String| out.lines().next().unwrap().split(' ').nth(2).unwrap().to_string();
+
+    let first = process_input(&mut room, "hello", 101, &cfg).unwrap();
+    assert!(first.ends_with(" fds 32\nENTITY: careful"), "{}", first);
+    let child = pid(first);
+    assert_eq!(pid(process_input(&mut room, "again", 102, &cfg).unwrap()), child);
+    assert_eq!(room.entity_state.kv["last"], "seen");
+    assert_eq!(room.entity_state.counters["calls"], 1);
+    let warned = room.memory.entries.iter().find(|e| e.content == "ENTITY: careful").unwrap();
+    assert_eq!(warned.metadata, serde_json::json!({"level": "WARN", "payload": {"n": 1}}));
+
+    let forked = process_input(&mut room, "fork", 102, &cfg).unwrap();
+    let grandchild = forked.lines().next().unwrap().rsplit(' ').next().unwrap().to_string();
+    let running = || std::fs::read_to_string(format!("/proc/{}/stat", grandchild)).is_ok_and(|s| !s.contains(") Z "));
+    assert!(running());
+
+    let out = process_input(&mut room, "die", 103, &cfg).unwrap();
+    assert!(out.starts_with("FATAL: ENTITY_CRASHED (entity process exited with exit status: 3)\n"), "{}", out);
+    let started = Instant::now();
+    while running() && started.elapsed() < Duration::from_secs(2) {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert!(!running(), "the child's process group outlived it");
+    let respawned = pid(process_input(&mut room, "back", 104, &cfg).unwrap());
+    assert_ne!(respawned, child);
+
+    let out = process_input(&mut room, "hang", 105, &cfg).unwrap();
+    assert!(out.starts_with("ERROR: ENTITY_TIMEOUT (1 seconds)"));
+    assert_ne!(pid(process_input(&mut room, "back", 106, &cfg).unwrap()), respawned);
+
+    assert_eq!(room.memory.count_of(EntryType::INPUT), 5);
+    assert_eq!(room.metadata.total_errors, 2);
+
+    let r = history::reconstruct(&room, 106).unwrap();
+    assert!(!r.supported);
+    assert_eq!(r.replayed, 0);
+    assert!(!r.exact());
+}
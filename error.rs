@@ -0,0 +1,34 @@
+use thiserror::Error;
+
+/// Errors surfaced to the operator verbatim; the display text is the protocol-level error line.
+#[derive(Debug, Error)]
+pub enum RoomError {
+    #[error("ERROR: INVALID_INPUT_ENCODING")]
+    InvalidInputEncoding,
+    #[error("ERROR: INPUT_SIZE_EXCEEDED (max {max} bytes)")]
+    InputSizeExceeded { max: u64 },
+    #[error("ERROR: ROOM_SUSPENDED")]
+    Suspended,
+    #[error("ERROR: ROOM_CORRUPTED")]
+    Corrupted,
+    #[error("ERROR: ROOM_LIMIT_REACHED (max {max} rooms)")]
+    RoomLimitReached { max: usize },
+    #[error("ERROR: ROOM_MEMORY_LIMIT_EXCEEDED (requested {requested} bytes, max {max})")]
+    RoomMemoryLimitExceeded { requested: u64, max: u64 },
+    #[error("ERROR: GLOBAL_MEMORY_EXCEEDED (requested {requested} bytes, {available} of {limit} available)")]
+    GlobalMemoryExceeded { requested: u64, available: u64, limit: u64 },
+    #[error("ERROR: QUEUE_FULL (max {max} pending)")]
+    QueueFull { max: usize },
+    #[error("ERROR: PINNED_LIMIT_EXCEEDED (requested {requested} bytes, max {max})")]
+    PinnedLimitExceeded { requested: u64, max: u64 },
+    #[error("ERROR: COUNTER_OVERFLOW ({name})")]
+    CounterOverflow { name: String },
+    #[error("ERROR: ENTITY_TIMEOUT ({seconds} seconds)")]
+    EntityTimeout { seconds: u64 },
+    #[error("FATAL: ENTITY_CRASHED ({message})")]
+    EntityCrashed { message: String },
+    #[error("ERROR: UNSUPPORTED_COMPRESSION ({name}; supported: deflate, none)")]
+    UnsupportedCompression { name: String },
+    #[error("ERROR: UNKNOWN_ENTITY ({name})")]
+    UnknownEntity { name: String },
+}
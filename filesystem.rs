@@ -1,9 +1,16 @@
-use super::{Persistence, RoomSummary};
+use super::fsck::{self, FsckReport, IssueKind};
+use super::text_index::TextIndex;
+use super::{checksum, Persistence, RoomSummary};
+use crate::query::SearchHit;
 use crate::room::{Room, RoomState};
 use anyhow::Context;
 use std::fs;
 use std::path::{Path, PathBuf};
 
+const STATE_FILE: &str = "room.json";
+const CHECKSUM_FILE: &str = "room.sha256";
+const INDEX_FILE: &str = "memory.idx";
+
 pub struct FilesystemPersistence {
     root: PathBuf,
 }
@@ -16,6 +23,11 @@ impl FilesystemPersistence {
     fn room_dir(&self, id: &str) -> PathBuf {
         self.root.join(id)
     }
+
+    fn load_index(&self, id: &str) -> Option<TextIndex> {
+        let raw = fs::read(self.room_dir(id).join(INDEX_FILE)).ok()?;
+        serde_json::from_slice(&raw).ok()
+    }
 }
 
 impl Persistence for FilesystemPersistence {
@@ -31,7 +43,7 @@ impl Persistence for FilesystemPersistence {
             let entry = entry?;
             if !entry.file_type()?.is_dir() { continue; }
             let id = entry.file_name().to_string_lossy().to_string();
-            let meta_path = self.room_dir(&id).join("room.json");
+            let meta_path = self.room_dir(&id).join(STATE_FILE);
             if !meta_path.exists() { continue; }
             let raw = fs::read_to_string(&meta_path)?;
             let room: Room = serde_json::from_str(&raw)?;
@@ -50,17 +62,25 @@ impl Persistence for FilesystemPersistence {
     }
 
     fn load_room(&self, id: &str) -> anyhow::Result<Room> {
-        let path = self.room_dir(id).join("room.json");
+        let path = self.room_dir(id).join(STATE_FILE);
         let raw = fs::read_to_string(&path).with_context(|| format!("missing room state: {}", path.display()))?;
-        Ok(serde_json::from_str(&raw)?)
+        let mut room: Room = serde_json::from_str(&raw)?;
+        room.memory.normalize_seqs()?;
+        room.config.normalize_compression();
+        Ok(room)
     }
 
     fn save_room(&self, room: &Room) -> anyhow::Result<()> {
         let dir = self.room_dir(&room.id);
         fs::create_dir_all(&dir)?;
-        let path = dir.join("room.json");
+        let path = dir.join(STATE_FILE);
         let raw = serde_json::to_string_pretty(room)?;
-        fs::write(&path, raw)?;
+        fs::write(&path, &raw)?;
+        fs::write(dir.join(CHECKSUM_FILE), checksum(&raw))?;
+        if self.load_index(&room.id).is_none_or(|idx| idx.is_stale(&room.memory)) {
+            let idx = TextIndex::build(&room.memory)?;
+            fs::write(dir.join(INDEX_FILE), serde_json::to_vec(&idx)?)?;
+        }
         Ok(())
     }
 
@@ -71,4 +91,80 @@ impl Persistence for FilesystemPersistence {
         }
         Ok(())
     }
+
+    fn search_memory(&self, id: &str, text: &str, limit: usize) -> anyhow::Result<Vec<SearchHit>> {
+        let room = self.load_room(id)?;
+        let idx = match self.load_index(id) {
+            Some(idx) if !idx.is_stale(&room.memory) => idx,
+            _ => TextIndex::build(&room.memory)?,
+        };
+        idx.search(&room.memory, text, limit)
+    }
+
+    fn fsck(&self, repair: bool) -> anyhow::Result<FsckReport> {
+        self.init()?;
+        let mut report = FsckReport::default();
+        for entry in fs::read_dir(&self.root)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !entry.file_type()?.is_dir() {
+                report.push(&name, IssueKind::STRAY_FILE, "file in persistence root", false);
+                continue;
+            }
+            let dir = self.room_dir(&name);
+            let meta_path = dir.join(STATE_FILE);
+            if !meta_path.exists() {
+                report.push(&name, IssueKind::ORPHAN_DIRECTORY, format!("no {} present", STATE_FILE), false);
+                continue;
+            }
+            for f in fs::read_dir(&dir)? {
+                let f = f?.file_name().to_string_lossy().to_string();
+                if f != STATE_FILE && f != CHECKSUM_FILE && f != INDEX_FILE {
+                    report.push(&name, IssueKind::STRAY_FILE, format!("unexpected file: {}", f), false);
+                }
+            }
+
+            report.rooms_checked += 1;
+            let raw = match fs::read_to_string(&meta_path) {
+                Ok(raw) => raw,
+                Err(e) => {
+                    report.push(&name, IssueKind::UNREADABLE, e.to_string(), false);
+                    continue;
+                }
+            };
+            let mut room: Room = match serde_json::from_str(&raw) {
+                Ok(room) => room,
+                Err(e) => {
+                    report.push(&name, IssueKind::DESERIALIZE_FAILED, e.to_string(), false);
+                    continue;
+                }
+            };
+            if room.id != name {
+                report.push(&name, IssueKind::ID_MISMATCH, format!("directory={} room.id={}", name, room.id), false);
+                continue;
+            }
+
+            // Never rewrite a room whose bytes no longer match what we last saved.
+            let mut can_repair = repair;
+            let mut changed = false;
+            match fs::read_to_string(dir.join(CHECKSUM_FILE)) {
+                Ok(stored) if stored.trim() == checksum(&raw) => {}
+                Ok(stored) => {
+                    report.push(&name, IssueKind::CHECKSUM_MISMATCH,
+                        format!("stored={} actual={}", stored.trim(), checksum(&raw)), false);
+                    can_repair = false;
+                }
+                Err(_) => {
+                    report.push(&name, IssueKind::CHECKSUM_MISSING, format!("no {} present", CHECKSUM_FILE), repair);
+                    changed = repair;
+                }
+            }
+
+            changed |= fsck::check_room(&mut room, can_repair, &mut report);
+            if changed {
+                self.save_room(&room)?;
+            }
+        }
+        Ok(report)
+    }
 }
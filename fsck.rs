@@ -0,0 +1,120 @@
+use crate::memory::EntryType;
+use crate::room::Room;
+use serde::Serialize;
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub enum IssueKind {
+    UNREADABLE,
+    DESERIALIZE_FAILED,
+    CHECKSUM_MISSING,
+    CHECKSUM_MISMATCH,
+    ID_MISMATCH,
+    MEMORY_USAGE_MISMATCH,
+    CAPACITY_MISMATCH,
+    RETENTION_MISMATCH,
+    COUNTER_MISMATCH,
+    ORPHAN_DIRECTORY,
+    STRAY_FILE,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FsckIssue {
+    /// Room id, or the directory/file name when the problem is not tied to a loadable room.
+    pub location: String,
+    pub kind: IssueKind,
+    pub detail: String,
+    pub repaired: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FsckReport {
+    pub rooms_checked: usize,
+    pub issues: Vec<FsckIssue>,
+}
+
+impl FsckReport {
+    pub fn push(&mut self, location: &str, kind: IssueKind, detail: impl Into<String>, repaired: bool) {
+        self.issues.push(FsckIssue { location: location.to_string(), kind, detail: detail.into(), repaired });
+    }
+
+    pub fn repaired(&self) -> usize {
+        self.issues.iter().filter(|i| i.repaired).count()
+    }
+
+    pub fn unrepaired(&self) -> usize {
+        self.issues.len() - self.repaired()
+    }
+}
+
+/// Verify the fields of a room that can be recomputed from its own contents.
+/// With `repair`, fixes them in place; returns true if the room was modified.
+pub fn check_room(room: &mut Room, repair: bool, report: &mut FsckReport) -> bool {
+    let mut changed = false;
+
+    let actual = room.memory.computed_usage();
+    if room.memory.usage != actual {
+        report.push(&room.id, IssueKind::MEMORY_USAGE_MISMATCH,
+            format!("memory.usage={} sum_of_entries={}", room.memory.usage, actual), repair);
+        if repair {
+            room.memory.usage = actual;
+            changed = true;
+        }
+    }
+
+    if room.memory.capacity != room.config.memory_limit {
+        report.push(&room.id, IssueKind::CAPACITY_MISMATCH,
+            format!("memory.capacity={} config.memory_limit={}", room.memory.capacity, room.config.memory_limit), repair);
+        if repair {
+            room.memory.capacity = room.config.memory_limit;
+            changed = true;
+        }
+    }
+
+    if room.memory.max_entries != room.config.max_entries {
+        report.push(&room.id, IssueKind::CAPACITY_MISMATCH,
+            format!("memory.max_entries={} config.max_entries={}", room.memory.max_entries, room.config.max_entries), repair);
+        if repair {
+            room.memory.max_entries = room.config.max_entries;
+            changed = true;
+        }
+    }
+
+    if room.memory.pinned_capacity != room.config.max_pinned_bytes {
+        report.push(&room.id, IssueKind::CAPACITY_MISMATCH,
+            format!("memory.pinned_capacity={} config.max_pinned_bytes={}", room.memory.pinned_capacity, room.config.max_pinned_bytes), repair);
+        if repair {
+            room.memory.pinned_capacity = room.config.max_pinned_bytes;
+            changed = true;
+        }
+    }
+
+    if room.memory.retention != room.config.retention {
+        report.push(&room.id, IssueKind::RETENTION_MISMATCH,
+            "memory.retention differs from config.retention", repair);
+        if repair {
+            room.memory.retention = room.config.retention.clone();
+            changed = true;
+        }
+    }
+
+    // Totals can exceed the entry counts once old entries are evicted, but never fall below them.
+    let counters = [
+        ("total_inputs", EntryType::INPUT, &mut room.metadata.total_inputs),
+        ("total_outputs", EntryType::OUTPUT, &mut room.metadata.total_outputs),
+        ("total_errors", EntryType::ERROR, &mut room.metadata.total_errors),
+    ];
+    for (name, kind, stored) in counters {
+        let seen = room.memory.count_of(kind);
+        if *stored < seen {
+            report.push(&room.id, IssueKind::COUNTER_MISMATCH,
+                format!("metadata.{}={} {:?}_entries={}", name, *stored, kind, seen), repair);
+            if repair {
+                *stored = seen;
+                changed = true;
+            }
+        }
+    }
+
+    changed
+}
@@ -0,0 +1,101 @@
+use crate::entity::EntityState;
+use crate::memory::{EntryType, MemoryStore};
+use crate::room::Room;
+use serde::{Deserialize, Serialize};
+
+/// Checkpoints kept per room; the oldest is dropped past this.
+pub const MAX_CHECKPOINTS: usize = 16;
+
+/// Entity state as it stood once every memory entry below `seq` had been processed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub seq: u64,
+    pub timestamp: i64,
+    pub entity_state: EntityState,
+}
+
+#[derive(Debug, Clone)]
+pub struct Reconstruction {
+    pub at: i64,
+    pub entity_state: EntityState,
+    /// Timestamp of the checkpoint replay started from; None when replayed from creation.
+    pub base: Option<i64>,
+    pub replayed: usize,
+    /// Set when inputs needed for the replay have been evicted: the newest such sequence number.
+    pub evicted_input: Option<u64>,
+    /// False when the room's entity cannot replay inputs; the state is then the base's.
+    pub supported: bool,
+}
+
+impl Reconstruction {
+    pub fn exact(&self) -> bool {
+        self.supported && self.evicted_input.is_none()
+    }
+}
+
+/// Record a checkpoint of the room's current entity state.
+pub fn checkpoint(room: &mut Room, now: i64) {
+    room.checkpoints.push(Checkpoint {
+        seq: room.memory.next_seq(),
+        timestamp: now,
+        entity_state: room.entity_state.clone(),
+    });
+    if room.checkpoints.len() > MAX_CHECKPOINTS {
+        room.checkpoints.remove(0);
+    }
+}
+
+/// Rebuild entity state as of `at` by replaying INPUT entries through the entity, starting
+/// from the newest checkpoint taken at or before `at`, or from what `init` gives a new room of
+/// its kind. Entities that are not `replayable` are never run past `init`; their
+/// reconstruction stops at the base.
+pub fn reconstruct(room: &Room, at: i64) -> anyhow::Result<Reconstruction> {
+    if at < room.created_at {
+        anyhow::bail!("room did not exist at {} (created {})", at, room.created_at);
+    }
+    let entity = room.entity()?;
+    // Replay into a scratch copy so the real room's memory and counters are untouched.
+    let mut scratch = room.clone();
+    scratch.checkpoints.clear();
+    scratch.memory = MemoryStore::new(u64::MAX);
+    let base = room.checkpoints.iter().rev().find(|c| c.timestamp <= at);
+    let from_seq = match base {
+        Some(c) => {
+            scratch.entity_state = c.entity_state.clone();
+            c.seq
+        }
+        // Without a checkpoint the base is the state the entity was created with.
+        None => {
+            scratch.entity_state = EntityState::default();
+            entity.init(&mut scratch, room.created_at);
+            0
+        }
+    };
+
+    if !entity.replayable() {
+        return Ok(Reconstruction {
+            at,
+            entity_state: scratch.entity_state,
+            base: base.map(|c| c.timestamp),
+            replayed: 0,
+            evicted_input: None,
+            supported: false,
+        });
+    }
+
+    let mut replayed = 0;
+    for e in room.memory.entries_from(from_seq)? {
+        if e.kind != EntryType::INPUT || e.timestamp > at { continue; }
+        entity.handle_input(&mut scratch, &e.content, e.timestamp);
+        replayed += 1;
+    }
+
+    Ok(Reconstruction {
+        at,
+        entity_state: scratch.entity_state,
+        base: base.map(|c| c.timestamp),
+        replayed,
+        evicted_input: room.memory.input_evicted_upto.filter(|seq| *seq >= from_seq),
+        supported: true,
+    })
+}
@@ -0,0 +1,35 @@
+use crate::error::RoomError;
+use serde::{Deserialize, Serialize};
+
+/// Why a line was refused before reaching the entity.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Rejection {
+    INVALID_INPUT_ENCODING,
+    INPUT_SIZE_EXCEEDED,
+}
+
+impl Rejection {
+    /// The protocol line reported for this rejection.
+    pub fn error(self, max_input_size: u64) -> RoomError {
+        match self {
+            Rejection::INVALID_INPUT_ENCODING => RoomError::InvalidInputEncoding,
+            Rejection::INPUT_SIZE_EXCEEDED => RoomError::InputSizeExceeded { max: max_input_size },
+        }
+    }
+}
+
+/// Turn one line of raw transport bytes into entity input, in the order the README lists:
+/// UTF-8 validation, size check, null byte stripping and trailing whitespace normalization.
+/// The line terminator, if still attached, is not counted against `max_input_size`.
+pub fn preprocess(raw: &[u8], max_input_size: u64) -> Result<String, Rejection> {
+    let raw = raw.strip_suffix(b"\n").unwrap_or(raw);
+    let raw = raw.strip_suffix(b"\r").unwrap_or(raw);
+    let text = std::str::from_utf8(raw).map_err(|_| Rejection::INVALID_INPUT_ENCODING)?;
+    if text.len() as u64 > max_input_size {
+        return Err(Rejection::INPUT_SIZE_EXCEEDED);
+    }
+    let mut text = text.replace('\0', "");
+    text.truncate(text.trim_end().len());
+    Ok(text)
+}
@@ -2,7 +2,16 @@ pub mod cli;
 pub mod config;
 pub mod room;
 pub mod memory;
+pub mod query;
+pub mod command;
+pub mod rules;
 pub mod entity;
+pub mod history;
+pub mod input;
+pub mod redact;
+pub mod error;
+pub mod session;
+pub mod process;
 pub mod persistence;
 #[cfg(feature = "daemon")]
 pub mod daemon;
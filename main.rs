@@ -1,24 +1,16 @@
 use anyhow::Context;
-use backrooms_terminal::{cli::{Cli, Commands}, config::{Config, Backend}, entity::Entity, persistence::{Persistence}, room::{Room, RoomConfig, RoomMetadata, RoomState}};
+use backrooms_terminal::{cli::{AtView, Cli, Commands, MemoryAction}, config::{Config, Backend}, persistence::{Persistence}, room::{Room, RoomState}};
+use backrooms_terminal::entity::{self, parse_output_level, Output, OutputLevel, OutputSink};
+use backrooms_terminal::error::RoomError;
+use backrooms_terminal::history;
+use backrooms_terminal::memory;
+use backrooms_terminal::process;
+use backrooms_terminal::redact;
+use backrooms_terminal::query::{parse_entry_type, MemoryQuery, MetaPredicate};
+use backrooms_terminal::session::{self, make_room_id, now_ts};
 use clap::Parser;
-use sha2::{Digest, Sha256};
-use std::io::{self, Write, Read};
+use std::io::{self, BufRead, Write, Read};
 use std::path::PathBuf;
-use time::OffsetDateTime;
-
-fn now_ts() -> i64 {
-    OffsetDateTime::now_utc().unix_timestamp()
-}
-
-fn make_room_id(origin: &str) -> String {
-    let ts = now_ts().to_string();
-    let seed = uuid::Uuid::new_v4().to_string();
-    let mut h = Sha256::new();
-    h.update(ts.as_bytes());
-    h.update(seed.as_bytes());
-    h.update(origin.as_bytes());
-    hex::encode(h.finalize())
-}
 
 fn parse_size(s: &str) -> anyhow::Result<u64> {
     let s = s.trim().to_uppercase();
@@ -43,9 +35,38 @@ fn persistence_from_cfg(cfg: &Config) -> anyhow::Result<Box<dyn Persistence>> {
     }
 }
 
+/// Writes each line of entity output as it is emitted, keeping the first write error.
+struct LineWriter<'a> {
+    w: &'a mut dyn Write,
+    err: Option<io::Error>,
+    json: bool,
+    min_level: OutputLevel,
+}
+
+impl<'a> LineWriter<'a> {
+    fn text(w: &'a mut dyn Write) -> Self {
+        Self { w, err: None, json: false, min_level: OutputLevel::DEBUG }
+    }
+}
+
+impl OutputSink for LineWriter<'_> {
+    fn send(&mut self, output: Output) {
+        if self.err.is_some() || output.level < self.min_level {
+            return;
+        }
+        let line = if self.json {
+            serde_json::to_string(&output).unwrap_or_default()
+        } else {
+            output.text
+        };
+        self.err = writeln!(self.w, "{line}").and_then(|_| self.w.flush()).err();
+    }
+}
+
 fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
     let cfg = Config::load(cli.config.as_deref())?;
+    process::register_configured(&cfg.entity);
     let persistence = persistence_from_cfg(&cfg)?;
     persistence.init()?;
 
@@ -58,42 +79,32 @@ fn main() -> anyhow::Result<()> {
             println!("SCANNING EXISTING ROOMS: {} FOUND", rooms.len());
             println!("READY");
         }
-        Commands::Create { memory_limit, timeout, compression, name } => {
-            let origin = format!("pid:{} user:{} host:{}",
-                std::process::id(),
-                std::env::var("USER").unwrap_or_else(|_| "unknown".to_string()),
-                hostname::get().ok().and_then(|h| h.into_string().ok()).unwrap_or_else(|| "unknown".to_string())
-            );
+        Commands::Create { memory_limit, timeout, compression, name, entity } => {
+            let user = std::env::var("USER").unwrap_or_else(|_| "unknown".to_string());
+            let host = hostname::get().ok().and_then(|h| h.into_string().ok()).unwrap_or_else(|| "unknown".to_string());
+            let origin = format!("pid:{} user:{} host:{}", std::process::id(), user, host);
             let id = make_room_id(&origin);
 
-            let mut rc = RoomConfig::default();
-            rc.memory_limit = memory_limit.as_deref().map(parse_size).transpose()?.unwrap_or(cfg.limits.max_room_memory);
-            rc.timeout_seconds = timeout.unwrap_or(cfg.limits.entity_timeout);
-            rc.compression = compression.unwrap_or_else(|| cfg.persistence.compression.clone());
-            rc.max_input_size = cfg.limits.max_input_size as u64;
-
-            let now = now_ts();
+            let mut rc = session::room_config_from(&cfg);
+            if let Some(limit) = memory_limit.as_deref().map(parse_size).transpose()? {
+                rc.memory_limit = limit;
+            }
+            if let Some(t) = timeout {
+                rc.timeout_seconds = t;
+            }
+            if let Some(c) = compression {
+                memory::check_compression(&c)?;
+                rc.compression = c;
+            }
+            if let Some(kind) = entity {
+                if entity::resolve(&kind).is_none() {
+                    return Err(RoomError::UnknownEntity { name: kind }.into());
+                }
+                rc.entity = kind;
+            }
 
-            let room = Room{
-                id: id.clone(),
-                created_at: now,
-                last_active: now,
-                state: RoomState::ACTIVE,
-                config: rc.clone(),
-                memory: backrooms_terminal::memory::MemoryStore::new(rc.memory_limit),
-                entity_state: backrooms_terminal::entity::EntityState::default(),
-                metadata: RoomMetadata{
-                    creation_timestamp: now,
-                    creator_pid: std::process::id(),
-                    creator_user: std::env::var("USER").unwrap_or_else(|_| "unknown".to_string()),
-                    creator_host: hostname::get().ok().and_then(|h| h.into_string().ok()).unwrap_or_else(|| "unknown".to_string()),
-                    total_inputs: 0,
-                    total_outputs: 0,
-                    total_errors: 0,
-                    last_error: None,
-                    state_version: 1,
-                },
-            };
+            let room = session::new_room(id.clone(), rc.clone(), now_ts(), user, host);
+            session::check_create_limits(&*persistence, &cfg.limits, &room)?;
 
             persistence.save_room(&room)?;
             println!("ROOM CREATED: {}", id);
@@ -101,16 +112,18 @@ fn main() -> anyhow::Result<()> {
                 println!("ALIAS: {}", alias);
             }
             println!("CONFIG: memory_limit={} timeout={} compression={}", rc.memory_limit, rc.timeout_seconds, rc.compression);
+            println!("ENTITY: {} {}", rc.entity, room.entity_state.version);
             println!("STATE: ACTIVE");
             println!("ENTITY: INITIALIZED");
         }
-        Commands::Enter { room_id, output, readonly } => {
+        Commands::Enter { room_id, output, readonly, format, min_level } => {
+            let min_level = min_level.as_deref().map(parse_output_level).transpose()?.unwrap_or(OutputLevel::DEBUG);
+            let json = format.as_deref() == Some("json");
             let mut room = persistence.load_room(&room_id)?;
-            if room.state == RoomState::SUSPENDED {
-                anyhow::bail!("ERROR: ROOM_SUSPENDED");
-            }
-            if room.state == RoomState::CORRUPTED {
-                anyhow::bail!("ERROR: ROOM_CORRUPTED");
+            session::check_enterable(&room)?;
+            session::enter_room(&mut room, &cfg, now_ts());
+            if !readonly {
+                persistence.save_room(&room)?;
             }
             println!("ENTERING ROOM");
             let mut out: Box<dyn Write> = if let Some(p) = output {
@@ -119,38 +132,37 @@ fn main() -> anyhow::Result<()> {
                 Box::new(io::stdout())
             };
 
-            let stdin = io::stdin();
+            // Read bytes, not strings: invalid UTF-8 is rejected per line by preprocessing
+            // instead of ending the session.
+            let mut stdin = io::stdin().lock();
             loop {
                 write!(io::stdout(), "> ")?;
                 io::stdout().flush()?;
-                let mut buf = String::new();
-                if stdin.read_line(&mut buf)? == 0 { break; }
-                let input = buf.trim_end_matches(['\n','\r']);
-                if input == "exit" || input == "quit" { break; }
-
-                let now = now_ts();
-                room.last_active = now;
-                room.metadata.total_inputs += 1;
+                let mut buf = Vec::new();
+                if stdin.read_until(b'\n', &mut buf)? == 0 { break; }
+                let input = buf.trim_ascii_end();
+                if input == b"exit" || input == b"quit" { break; }
 
-                let resp = Entity::handle_input(&mut room, input, now);
-                if let Some(line) = resp {
-                    room.metadata.total_outputs += 1;
-                    writeln!(out, "{line}")?;
-                    room.memory.append(backrooms_terminal::memory::MemoryEntry{
-                        timestamp: now,
-                        kind: backrooms_terminal::memory::EntryType::OUTPUT,
-                        content: line.clone(),
-                        metadata: serde_json::json!({}),
-                    });
+                let mut sink = LineWriter { json, min_level, ..LineWriter::text(&mut *out) };
+                let before = room.memory.usage;
+                session::stream_raw_input(&mut room, &buf, now_ts(), &cfg, &mut sink);
+                if let Some(e) = sink.err {
+                    return Err(e.into());
+                }
+                if room.memory.usage > before {
+                    session::enforce_global_memory(&*persistence, &mut room, &cfg, now_ts())?;
                 }
-
-                room.memory.truncate_to_fit();
 
                 if !readonly {
                     persistence.save_room(&room)?;
                 }
             }
+            room.transition(RoomState::IDLE, "exit", now_ts());
+            if !readonly {
+                persistence.save_room(&room)?;
+            }
             println!("EXITING ROOM");
+            println!("ROOM STATE: {:?}", room.state);
         }
         Commands::List { state, limit, format } => {
             let mut rooms = persistence.list_rooms()?;
@@ -182,6 +194,7 @@ fn main() -> anyhow::Result<()> {
                     println!("CREATED: {}", room.created_at);
                     println!("LAST_ACTIVE: {}", room.last_active);
                     println!("MEMORY: {} / {}", room.memory.usage, room.memory.capacity);
+                    println!("ENTITY: {} {}", room.config.entity, room.entity_state.version);
                     println!("INPUTS: {}", room.metadata.total_inputs);
                     println!("OUTPUTS: {}", room.metadata.total_outputs);
                 }
@@ -189,15 +202,13 @@ fn main() -> anyhow::Result<()> {
         }
         Commands::Suspend { room_id } => {
             let mut room = persistence.load_room(&room_id)?;
-            room.state = RoomState::SUSPENDED;
-            room.metadata.state_version += 1;
+            room.transition(RoomState::SUSPENDED, "suspend", now_ts());
             persistence.save_room(&room)?;
             println!("STATE: SUSPENDED");
         }
         Commands::Resume { room_id } => {
             let mut room = persistence.load_room(&room_id)?;
-            room.state = RoomState::ACTIVE;
-            room.metadata.state_version += 1;
+            room.transition(RoomState::ACTIVE, "resume", now_ts());
             persistence.save_room(&room)?;
             println!("STATE: ACTIVE");
         }
@@ -205,6 +216,12 @@ fn main() -> anyhow::Result<()> {
             if !confirm {
                 anyhow::bail!("refusing to destroy without --confirm");
             }
+            // A room that no longer loads is still destroyed; there is just no entity to notify.
+            if let Ok(mut room) = persistence.load_room(&room_id) {
+                if let Ok(e) = room.entity() {
+                    e.shutdown(&mut room, now_ts());
+                }
+            }
             persistence.delete_room(&room_id)?;
             println!("ROOM TERMINATED");
         }
@@ -214,19 +231,51 @@ fn main() -> anyhow::Result<()> {
             match fmt.as_str() {
                 "jsonl" => {
                     let mut f = std::fs::File::create(&output)?;
-                    for e in room.memory.entries {
+                    for e in room.memory.all_entries()? {
                         let line = serde_json::to_string(&e)?;
                         writeln!(f, "{line}")?;
                     }
                 }
                 "json" => {
-                    std::fs::write(&output, serde_json::to_string_pretty(&room.memory.entries)?)?;
+                    std::fs::write(&output, serde_json::to_string_pretty(&room.memory.all_entries()?)?)?;
                 }
                 _ => anyhow::bail!("unsupported export format: {}", fmt),
             }
             println!("EXPORTED: {}", output.display());
         }
-        Commands::Stats { room_id } => {
+        Commands::Redact { room_id, pattern, replacement } => {
+            let pattern = regex::Regex::new(&pattern)?;
+            let mut room = persistence.load_room(&room_id)?;
+            let report = redact::redact_room(&mut room, &pattern, &replacement, now_ts())?;
+            persistence.save_room(&room)?;
+            println!("REDACTED: {}", room.id);
+            println!("ENTRIES_REWRITTEN: {}", report.entries);
+            println!("CHECKPOINTS_REWRITTEN: {}", report.checkpoints);
+            println!("ENTITY_STATE_REWRITTEN: {}", if report.entity_state { "YES" } else { "NO" });
+            if !report.suffixed_keys.is_empty() {
+                println!("KEYS_SUFFIXED: {}", report.suffixed_keys.join(", "));
+            }
+        }
+        Commands::Stats { room_id, counter: Some(name) } => {
+            let room = persistence.load_room(&room_id)?;
+            let Some(value) = room.entity_state.counters.get(&name) else {
+                anyhow::bail!("no counter {} in room {}", name, room_id);
+            };
+            let history = room.entity_state.counter_history.get(&name).cloned().unwrap_or_default();
+            println!("COUNTER STATISTICS");
+            println!("ID: {}", room.id);
+            println!("COUNTER: {}", name);
+            println!("VALUE: {}", value);
+            println!("SAMPLES: {}", history.len());
+            match entity::counter_rate(&history) {
+                Some(rate) => println!("RATE: {:.3}/s", rate),
+                None => println!("RATE: n/a"),
+            }
+            for s in &history {
+                println!("{} {}", s.timestamp, s.value);
+            }
+        }
+        Commands::Stats { room_id, counter: None } => {
             let room = persistence.load_room(&room_id)?;
             println!("ROOM STATISTICS");
             println!("ID: {}", room.id);
@@ -235,9 +284,110 @@ fn main() -> anyhow::Result<()> {
             println!("LAST_ACTIVE: {}", room.last_active);
             println!("MEMORY_USAGE: {} bytes", room.memory.usage);
             println!("MEMORY_CAPACITY: {} bytes", room.memory.capacity);
-            println!("ENTRIES: {}", room.memory.entries.len());
+            println!("ENTRIES: {}", room.memory.len());
+            println!("COMPRESSED_BLOCKS: {}", room.memory.blocks.len());
+            for (kind, (count, bytes)) in room.memory.usage_by_type() {
+                println!("USAGE_{:?}: {} entries, {} bytes", kind, count, bytes);
+            }
+            let packed: u64 = room.memory.blocks.iter().map(|b| b.entry_count).sum();
+            let packed_bytes: u64 = room.memory.blocks.iter().map(|b| b.size()).sum();
+            println!("USAGE_COMPRESSED: {} entries, {} bytes", packed, packed_bytes);
+            println!("PINNED_USAGE: {} / {} bytes", room.memory.pinned_usage(), room.memory.pinned_capacity);
             println!("TOTAL_INPUTS: {}", room.metadata.total_inputs);
             println!("TOTAL_OUTPUTS: {}", room.metadata.total_outputs);
+            println!("TIMEOUTS: {}", room.metadata.total_timeouts);
+            for (reason, n) in &room.metadata.rejected_inputs {
+                println!("REJECTED_{:?}: {}", reason, n);
+            }
+        }
+        Commands::Memory { room_id, action } => match action {
+            MemoryAction::Search { kinds, since, until, contains, regex, predicates, limit, offset, reverse, format } => {
+                let q = MemoryQuery{
+                    kinds: kinds.iter().map(|k| parse_entry_type(k)).collect::<anyhow::Result<_>>()?,
+                    since,
+                    until,
+                    contains,
+                    regex: regex.as_deref().map(regex::Regex::new).transpose()?,
+                    metadata: predicates.iter().map(|p| MetaPredicate::parse(p)).collect::<anyhow::Result<_>>()?,
+                    limit,
+                    offset,
+                    reverse,
+                };
+                let hits = persistence.query_memory(&room_id, &q)?;
+                match format.as_deref().unwrap_or("text") {
+                    "json" => println!("{}", serde_json::to_string_pretty(&hits)?),
+                    _ => {
+                        for e in &hits {
+                            println!("{} {:?} {}", e.timestamp, e.kind, e.content);
+                        }
+                        println!("MATCHES: {}", hits.len());
+                    }
+                }
+            }
+            MemoryAction::Find { terms, limit, format } => {
+                let hits = persistence.search_memory(&room_id, &terms, limit)?;
+                match format.as_deref().unwrap_or("text") {
+                    "json" => println!("{}", serde_json::to_string_pretty(&hits)?),
+                    _ => {
+                        for h in &hits {
+                            println!("{} {:?} {:.3} {}", h.timestamp, h.kind, h.score, h.snippet);
+                        }
+                        println!("MATCHES: {}", hits.len());
+                    }
+                }
+            }
+            MemoryAction::Pin { timestamp } | MemoryAction::Unpin { timestamp } => {
+                let pinned = matches!(action, MemoryAction::Pin { .. });
+                let mut room = persistence.load_room(&room_id)?;
+                let n = room.memory.set_pinned(timestamp, pinned)?;
+                if n == 0 {
+                    anyhow::bail!("no memory entries at {} in room {}", timestamp, room_id);
+                }
+                persistence.save_room(&room)?;
+                println!("{}: {} entries at {}", if pinned { "PINNED" } else { "UNPINNED" }, n, timestamp);
+                println!("PINNED_USAGE: {} / {} bytes", room.memory.pinned_usage(), room.memory.pinned_capacity);
+            }
+        },
+        Commands::At { room_id, timestamp, view } => {
+            let room = persistence.load_room(&room_id)?;
+            let r = history::reconstruct(&room, timestamp)?;
+            println!("ROOM AT: {}", r.at);
+            match r.base {
+                Some(ts) => println!("BASE: CHECKPOINT {}", ts),
+                None => println!("BASE: CREATION"),
+            }
+            println!("INPUTS REPLAYED: {}", r.replayed);
+            match r.evicted_input {
+                _ if !r.supported => {
+                    println!("RECONSTRUCTION: UNSUPPORTED");
+                    println!("ENTITY: {} does not replay inputs; showing the base state", room.config.entity);
+                }
+                None => println!("RECONSTRUCTION: EXACT"),
+                Some(seq) => {
+                    println!("RECONSTRUCTION: INCOMPLETE");
+                    println!("HISTORY_EVICTED: inputs up to seq {} are no longer held", seq);
+                }
+            }
+            match view {
+                AtView::Inspect => {
+                    let mut kv: Vec<_> = r.entity_state.kv.iter().collect();
+                    kv.sort();
+                    let mut counters: Vec<_> = r.entity_state.counters.iter().collect();
+                    counters.sort();
+                    println!("KV: {}", kv.len());
+                    for (k, v) in kv {
+                        println!("  {} = {}", k, v);
+                    }
+                    println!("COUNTERS: {}", counters.len());
+                    for (k, v) in counters {
+                        println!("  {} = {}", k, v);
+                    }
+                }
+                AtView::Recall { key } => match r.entity_state.kv.get(&key) {
+                    Some(v) => println!("ENTITY: {}", v),
+                    None => println!("ENTITY: No such entry."),
+                },
+            }
         }
         Commands::Compare { id1, id2 } => {
             let a = persistence.load_room(&id1)?;
@@ -245,8 +395,8 @@ fn main() -> anyhow::Result<()> {
             println!("COMPARING ROOMS");
             println!("ROOM A: {} {:?}", a.id, a.state);
             println!("ROOM B: {} {:?}", b.id, b.state);
-            println!("MEMORY A: {} entries {} bytes", a.memory.entries.len(), a.memory.usage);
-            println!("MEMORY B: {} entries {} bytes", b.memory.entries.len(), b.memory.usage);
+            println!("MEMORY A: {} entries {} bytes", a.memory.len(), a.memory.usage);
+            println!("MEMORY B: {} entries {} bytes", b.memory.len(), b.memory.usage);
             println!("NO SHARED MEMORY DETECTED");
         }
         Commands::Backup { room_id, output } => {
@@ -265,49 +415,71 @@ fn main() -> anyhow::Result<()> {
             let tar_gz = std::fs::File::open(&path)?;
             let dec = flate2::read::GzDecoder::new(tar_gz);
             let mut archive = tar::Archive::new(dec);
-            archive.unpack(&cfg.persistence.path)?;
+            let mut restored: Vec<String> = vec![];
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+                let path = entry.path()?.into_owned();
+                if let Some(id) = path.components().next().map(|c| c.as_os_str().to_string_lossy().to_string()) {
+                    if !restored.contains(&id) {
+                        restored.push(id);
+                    }
+                }
+                entry.unpack_in(&cfg.persistence.path)?;
+            }
+            for id in restored {
+                match persistence.load_room(&id) {
+                    Ok(mut room) => {
+                        room.transition(room.state, "restore", now_ts());
+                        persistence.save_room(&room)?;
+                        println!("RESTORED: {}", id);
+                    }
+                    Err(e) => eprintln!("[WARN] restored {} but could not load it: {}", id, e),
+                }
+            }
             println!("RESTORE COMPLETE");
         }
         Commands::Batch { file } => {
             let input = if let Some(p) = file {
-                std::fs::read_to_string(p)?
+                std::fs::read(p)?
             } else {
-                let mut buf = String::new();
-                io::stdin().read_to_string(&mut buf)?;
+                let mut buf = Vec::new();
+                io::stdin().read_to_end(&mut buf)?;
                 buf
             };
 
+            // Very small batch language: "create", "enter <id>" and "exit"; lines in between are entity inputs.
             let mut last_room: Option<String> = None;
-            for line in input.lines() {
-                let line = line.trim();
-                if line.is_empty() { continue; }
-                // Very small batch language: supports "create" and "enter <id>" and entity inputs.
+            let mut current: Option<Room> = None;
+            for raw in input.split(|b| *b == b'\n') {
+                let raw = raw.trim_ascii();
+                if raw.is_empty() { continue; }
+                if let Some(room) = current.as_mut() {
+                    if raw == b"exit" || raw == b"quit" {
+                        room.transition(RoomState::IDLE, "exit", now_ts());
+                        persistence.save_room(room)?;
+                        current = None;
+                        println!("EXITING ROOM");
+                        continue;
+                    }
+                    let mut stdout = io::stdout();
+                    let mut sink = LineWriter::text(&mut stdout);
+                    let before = room.memory.usage;
+                    session::stream_raw_input(room, raw, now_ts(), &cfg, &mut sink);
+                    if let Some(e) = sink.err {
+                        return Err(e.into());
+                    }
+                    if room.memory.usage > before {
+                        session::enforce_global_memory(&*persistence, room, &cfg, now_ts())?;
+                    }
+                    persistence.save_room(room)?;
+                    continue;
+                }
+                let line = String::from_utf8_lossy(raw);
                 if line.starts_with("create") {
-                    // call create by reusing this process logic
-                    let origin = "batch".to_string();
-                    let id = make_room_id(&origin);
-                    let now = now_ts();
-                    let rc = RoomConfig::default();
-                    let room = Room{
-                        id: id.clone(),
-                        created_at: now,
-                        last_active: now,
-                        state: RoomState::ACTIVE,
-                        config: rc.clone(),
-                        memory: backrooms_terminal::memory::MemoryStore::new(rc.memory_limit),
-                        entity_state: backrooms_terminal::entity::EntityState::default(),
-                        metadata: RoomMetadata{
-                            creation_timestamp: now,
-                            creator_pid: std::process::id(),
-                            creator_user: "batch".to_string(),
-                            creator_host: "batch".to_string(),
-                            total_inputs: 0,
-                            total_outputs: 0,
-                            total_errors: 0,
-                            last_error: None,
-                            state_version: 1,
-                        },
-                    };
+                    let id = make_room_id("batch");
+                    let rc = session::room_config_from(&cfg);
+                    let room = session::new_room(id.clone(), rc, now_ts(), "batch".to_string(), "batch".to_string());
+                    session::check_create_limits(&*persistence, &cfg.limits, &room)?;
                     persistence.save_room(&room)?;
                     println!("ROOM CREATED: {}", id);
                     last_room = Some(id);
@@ -322,16 +494,50 @@ fn main() -> anyhow::Result<()> {
                     } else {
                         anyhow::bail!("invalid enter syntax");
                     };
-                    // subsequent lines until "exit" are routed
                     let mut room = persistence.load_room(&id)?;
+                    session::check_enterable(&room)?;
+                    session::enter_room(&mut room, &cfg, now_ts());
+                    persistence.save_room(&room)?;
                     println!("ENTERING ROOM");
+                    current = Some(room);
                     continue;
                 }
-                // batch entity input would require a room context; kept minimal here.
                 println!("IGNORED: {}", line);
             }
+            if let Some(mut room) = current {
+                room.transition(RoomState::IDLE, "exit", now_ts());
+                persistence.save_room(&room)?;
+            }
             println!("BATCH COMPLETE");
         }
+        Commands::Sweep => {
+            println!("SWEEPING ROOMS");
+            let changes = session::sweep(&*persistence, &cfg.lifecycle, now_ts())?;
+            for (id, from, to) in &changes {
+                println!("{} {:?} -> {:?}", id, from, to);
+            }
+            println!("TRANSITIONS: {}", changes.len());
+        }
+        Commands::Fsck { repair, format } => {
+            let report = persistence.fsck(repair)?;
+            match format.as_deref().unwrap_or("text") {
+                "json" => println!("{}", serde_json::to_string_pretty(&report)?),
+                _ => {
+                    println!("CHECKING ROOM STORE");
+                    println!("BACKEND: {:?}", cfg.persistence.backend);
+                    println!("ROOMS CHECKED: {}", report.rooms_checked);
+                    for i in &report.issues {
+                        let tag = if i.repaired { "REPAIRED" } else { "PROBLEM" };
+                        println!("[{}] {} {:?}: {}", tag, i.location, i.kind, i.detail);
+                    }
+                    println!("PROBLEMS: {}", report.issues.len());
+                    println!("REPAIRED: {}", report.repaired());
+                }
+            }
+            if report.unrepaired() > 0 {
+                anyhow::bail!("ERROR: FSCK_FAILED ({} unrepaired)", report.unrepaired());
+            }
+        }
         #[cfg(feature="daemon")]
         Commands::Daemon => {
             backrooms_terminal::daemon::run(cfg)?;
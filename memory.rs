@@ -1,21 +1,126 @@
+use crate::error::RoomError;
+use crate::query::MemoryQuery;
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{Read, Write};
+use std::time::Instant;
+
+pub const COMPRESSION_ALGORITHM: &str = "deflate";
+pub const COMPRESSION_LEVEL: u32 = 3;
+
+/// Check a room's `compression` setting: the packing algorithm, or "none" to disable packing.
+pub fn check_compression(name: &str) -> Result<(), RoomError> {
+    if name == COMPRESSION_ALGORITHM || name == "none" {
+        Ok(())
+    } else {
+        Err(RoomError::UnsupportedCompression { name: name.to_string() })
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MemoryStore {
-    pub entries: Vec<MemoryEntry>,
+    /// Uncompressed entries in sequence order. Entries of types with a retention rule are
+    /// never packed, so these can be older than some compressed blocks.
+    pub entries: VecDeque<MemoryEntry>,
+    /// Entries packed by the compression pass, oldest first.
+    #[serde(default)]
+    pub blocks: VecDeque<CompressedBlock>,
     pub capacity: u64,
     pub usage: u64,
+    /// Second bound next to `capacity`: the most entries held, counting packed ones.
+    #[serde(default = "unbounded")]
+    pub max_entries: usize,
+    /// Most bytes pinned entries may hold. They still count toward `usage`; this cap keeps
+    /// enough of `capacity` evictable for truncation to succeed.
+    #[serde(default = "default_pinned_capacity")]
+    pub pinned_capacity: u64,
+    /// Entries ever appended; also the sequence number the next entry gets.
+    #[serde(default)]
+    pub appended: u64,
+    /// Bumped whenever held entries are rewritten in place, so indexes know to rebuild.
+    #[serde(default)]
+    pub revision: u64,
+    /// Bumped by every compression pass, which moves entries from `entries` into `blocks`.
+    #[serde(default)]
+    pub compactions: u64,
+    /// After a compression pass that saved nothing, the sequence number the next pass waits for.
+    #[serde(default)]
+    pub compress_after: u64,
+    /// Sequence number of the newest INPUT entry evicted so far, if any.
+    #[serde(default)]
+    pub input_evicted_upto: Option<u64>,
+    #[serde(default)]
+    pub retention: RetentionPolicy,
+    /// Running totals for retention; None until the first check builds them.
+    #[serde(skip)]
+    tally: Option<RetentionTally>,
+}
+
+/// Count, bytes and oldest timestamp of the unpinned loose entries of each type with a bounded
+/// retention rule, kept up to date on append and eviction so inputs that break no rule need no
+/// scan. `policy` is the one they were built for; a different policy rebuilds them.
+#[derive(Debug, Clone, Default)]
+struct RetentionTally {
+    policy: RetentionPolicy,
+    types: BTreeMap<EntryType, TypeTally>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TypeTally {
+    count: usize,
+    bytes: u64,
+    /// Never newer than the oldest entry held; eviction can leave it older, which at worst
+    /// costs one scan that sets it right.
+    oldest: i64,
 }
 
+/// Per-type retention rules. Types without a rule are evicted oldest first under pressure.
+pub type RetentionPolicy = BTreeMap<EntryType, RetentionRule>;
+
+/// Limits for one entry type. Entries governed by a rule stay uncompressed so the rule
+/// always sees every one of them; past a limit, the oldest entries of the type go first.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct RetentionRule {
+    /// Seconds an entry is kept after its timestamp.
+    #[serde(default)]
+    pub max_age: Option<u64>,
+    #[serde(default)]
+    pub max_count: Option<usize>,
+    /// Serialized bytes held across all entries of the type.
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+    /// Never evict entries of this type, even when that holds the room over capacity.
+    #[serde(default)]
+    pub never_evict: bool,
+}
+
+fn unbounded() -> usize {
+    usize::MAX
+}
+
+pub fn default_pinned_capacity() -> u64 {
+    1024 * 1024
+}
+
+/// Bytes `"pinned":true,` adds to an entry's serialized size.
+const PINNED_FLAG_SIZE: u64 = 14;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MemoryEntry {
+    /// Assigned by `MemoryStore::append`; an entry keeps it until evicted.
+    #[serde(default)]
+    pub seq: u64,
     pub timestamp: i64,
     pub kind: EntryType,
     pub content: String,
     pub metadata: serde_json::Value,
+    /// Pinned entries are never compressed or evicted.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 #[serde(rename_all = "UPPERCASE")]
 pub enum EntryType {
     INPUT,
@@ -25,20 +130,550 @@ pub enum EntryType {
     ERROR,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompressedBlock {
+    #[serde(default)]
+    pub first_seq: u64,
+    #[serde(default)]
+    pub last_seq: u64,
+    pub first_timestamp: i64,
+    pub last_timestamp: i64,
+    pub entry_count: u64,
+    /// Usage the packed entries accounted for before compression.
+    pub raw_size: u64,
+    /// Hex-encoded deflate stream of the packed entries as a JSON array.
+    pub data: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CompressionReport {
+    pub started_at: i64,
+    pub entries_before: u64,
+    pub entries_after: u64,
+    pub entries_packed: u64,
+    pub size_before: u64,
+    pub size_after: u64,
+    pub duration_secs: f64,
+}
+
+impl MemoryEntry {
+    /// Bytes this entry counts for against the room's memory capacity: its serialized size,
+    /// so metadata and field overhead are paid for along with the content.
+    pub fn size(&self) -> u64 {
+        serde_json::to_vec(self).map(|v| v.len()).unwrap_or(self.content.len()) as u64
+    }
+}
+
+impl CompressedBlock {
+    fn pack(entries: &[MemoryEntry]) -> anyhow::Result<Self> {
+        let raw = serde_json::to_vec(entries)?;
+        let mut enc = DeflateEncoder::new(Vec::new(), Compression::new(COMPRESSION_LEVEL));
+        enc.write_all(&raw)?;
+        Ok(Self {
+            first_seq: entries.first().map(|e| e.seq).unwrap_or(0),
+            last_seq: entries.last().map(|e| e.seq).unwrap_or(0),
+            first_timestamp: entries.first().map(|e| e.timestamp).unwrap_or(0),
+            last_timestamp: entries.last().map(|e| e.timestamp).unwrap_or(0),
+            entry_count: entries.len() as u64,
+            raw_size: entries.iter().map(MemoryEntry::size).sum(),
+            data: hex::encode(enc.finish()?),
+        })
+    }
+
+    /// Compressed bytes this block counts for against the room's memory capacity.
+    pub fn size(&self) -> u64 {
+        (self.data.len() / 2) as u64
+    }
+
+    pub fn unpack(&self) -> anyhow::Result<Vec<MemoryEntry>> {
+        let bytes = hex::decode(&self.data)?;
+        let mut raw = Vec::new();
+        DeflateDecoder::new(bytes.as_slice()).read_to_end(&mut raw)?;
+        Ok(serde_json::from_slice(&raw)?)
+    }
+}
+
+impl RetentionRule {
+    /// True when the rule bounds the type at all; `never_evict` alone does not.
+    pub fn is_bounded(&self) -> bool {
+        !self.never_evict && (self.max_age.is_some() || self.max_count.is_some() || self.max_bytes.is_some())
+    }
+}
+
+impl RetentionTally {
+    fn build(policy: &RetentionPolicy, entries: &VecDeque<MemoryEntry>) -> Self {
+        let mut tally = Self { policy: policy.clone(), types: BTreeMap::new() };
+        for e in entries {
+            if tally.tracks(e) {
+                tally.add(e, e.size());
+            }
+        }
+        tally
+    }
+
+    fn tracks(&self, e: &MemoryEntry) -> bool {
+        !e.pinned && self.policy.get(&e.kind).is_some_and(RetentionRule::is_bounded)
+    }
+
+    fn add(&mut self, e: &MemoryEntry, size: u64) {
+        if !self.tracks(e) {
+            return;
+        }
+        let t = self.types.entry(e.kind).or_insert(TypeTally { count: 0, bytes: 0, oldest: e.timestamp });
+        t.count += 1;
+        t.bytes += size;
+        t.oldest = t.oldest.min(e.timestamp);
+    }
+
+    fn remove(&mut self, e: &MemoryEntry, size: u64) {
+        if !self.tracks(e) {
+            return;
+        }
+        if let Some(t) = self.types.get_mut(&e.kind) {
+            t.count = t.count.saturating_sub(1);
+            t.bytes = t.bytes.saturating_sub(size);
+        }
+    }
+
+    fn exceeded(&self, now: i64) -> bool {
+        self.types.iter().any(|(kind, t)| {
+            let rule = &self.policy[kind];
+            rule.max_age.is_some_and(|age| now.saturating_sub(t.oldest) > age as i64)
+                || rule.max_count.is_some_and(|max| t.count > max)
+                || rule.max_bytes.is_some_and(|max| t.bytes > max)
+        })
+    }
+}
+
+impl CompressionReport {
+    /// The operator-facing COMPRESSION_* lines.
+    pub fn lines(&self) -> Vec<String> {
+        let ratio = if self.size_after == 0 { 0.0 } else { self.size_before as f64 / self.size_after as f64 };
+        vec![
+            format!("COMPRESSION_STARTED: timestamp={}", self.started_at),
+            format!("COMPRESSION_ALGORITHM: {}", COMPRESSION_ALGORITHM),
+            format!("COMPRESSION_LEVEL: {}", COMPRESSION_LEVEL),
+            format!("ENTRIES_BEFORE: {}", self.entries_before),
+            format!("ENTRIES_AFTER: {}", self.entries_after),
+            format!("SIZE_BEFORE: {}", self.size_before),
+            format!("SIZE_AFTER: {}", self.size_after),
+            format!("COMPRESSION_RATIO: {:.2}", ratio),
+            format!("COMPRESSION_DURATION: {:.2}s", self.duration_secs),
+        ]
+    }
+}
+
 impl MemoryStore {
     pub fn new(capacity: u64) -> Self {
-        Self { entries: vec![], capacity, usage: 0 }
+        Self {
+            entries: VecDeque::new(),
+            blocks: VecDeque::new(),
+            capacity,
+            usage: 0,
+            max_entries: usize::MAX,
+            pinned_capacity: default_pinned_capacity(),
+            appended: 0,
+            revision: 0,
+            compactions: 0,
+            compress_after: 0,
+            input_evicted_upto: None,
+            retention: RetentionPolicy::new(),
+            tally: None,
+        }
+    }
+
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    pub fn with_retention(mut self, retention: RetentionPolicy) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    pub fn with_pinned_capacity(mut self, pinned_capacity: u64) -> Self {
+        self.pinned_capacity = pinned_capacity;
+        self
+    }
+
+    pub fn append(&mut self, mut entry: MemoryEntry) {
+        entry.seq = self.next_seq();
+        self.appended = entry.seq + 1;
+        let size = entry.size();
+        self.usage = self.usage.saturating_add(size);
+        if let Some(tally) = &mut self.tally {
+            tally.add(&entry, size);
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// Drop the entries appended from sequence number `from` on, as if they never were. Only
+    /// loose entries can be that new, so no block is touched.
+    pub fn rollback(&mut self, from: u64) {
+        while let Some(e) = self.entries.back().filter(|e| e.seq >= from) {
+            self.usage = self.usage.saturating_sub(e.size());
+            self.entries.pop_back();
+        }
+        self.appended = self.appended.min(from);
+        self.tally = None;
+    }
+
+    /// Sequence number the next appended entry will get.
+    pub fn next_seq(&self) -> u64 {
+        self.appended
+    }
+
+    /// Sequence number of the oldest entry held, compressed or not.
+    pub fn first_seq(&self) -> u64 {
+        let packed = self.blocks.front().map(|b| b.first_seq);
+        let loose = self.entries.front().map(|e| e.seq);
+        packed.into_iter().chain(loose).min().unwrap_or(self.appended)
+    }
+
+    /// Number of entries held, counting those packed into compressed blocks.
+    pub fn len(&self) -> u64 {
+        self.entries.len() as u64 + self.blocks.iter().map(|b| b.entry_count).sum::<u64>()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty() && self.blocks.is_empty()
+    }
+
+    /// Every entry in sequence order, with compressed blocks unpacked.
+    pub fn all_entries(&self) -> anyhow::Result<Vec<MemoryEntry>> {
+        let mut out = Vec::new();
+        for b in &self.blocks {
+            out.extend(b.unpack()?);
+        }
+        out.extend(self.entries.iter().cloned());
+        out.sort_by_key(|e| e.seq);
+        Ok(out)
+    }
+
+    /// Entries with sequence number `from` or later, unpacking blocks only when they hold some.
+    pub fn entries_from(&self, from: u64) -> anyhow::Result<Vec<MemoryEntry>> {
+        if self.blocks.back().is_none_or(|b| b.last_seq < from) {
+            return Ok(self.entries.iter().filter(|e| e.seq >= from).cloned().collect());
+        }
+        let mut out = self.all_entries()?;
+        out.retain(|e| e.seq >= from);
+        Ok(out)
+    }
+
+    /// Entries matching `q`, compressed blocks included.
+    pub fn query(&self, q: &MemoryQuery) -> anyhow::Result<Vec<MemoryEntry>> {
+        Ok(q.apply(self.all_entries()?))
+    }
+
+    /// Renumber stores saved before entries carried their own sequence number. Those numbered
+    /// entries by position, so the same numbering is restored here; other stores are untouched.
+    pub fn normalize_seqs(&mut self) -> anyhow::Result<()> {
+        let len = self.len();
+        let unnumbered = self.entries.iter().all(|e| e.seq == 0) && self.blocks.iter().all(|b| b.last_seq == 0);
+        let first = self.appended.max(len) - len;
+        if !unnumbered {
+            return Ok(());
+        }
+        let mut seq = first;
+        let mut blocks = VecDeque::new();
+        for b in &self.blocks {
+            let mut packed = b.unpack()?;
+            for e in &mut packed {
+                e.seq = seq;
+                seq += 1;
+            }
+            blocks.push_back(CompressedBlock::pack(&packed)?);
+        }
+        for e in &mut self.entries {
+            e.seq = seq;
+            seq += 1;
+        }
+        self.blocks = blocks;
+        self.appended = seq;
+        // Legacy usage counted content bytes only, so it is recomputed rather than adjusted.
+        self.usage = self.computed_usage();
+        Ok(())
+    }
+
+    /// Once usage crosses `threshold` of capacity, pack the oldest entries into a compressed
+    /// block until the uncompressed tail fits in half of the threshold budget. Entries of types
+    /// with a retention rule are left as they are.
+    /// Returns None when under the threshold or when packing would not save space. A pass that
+    /// saves nothing is not retried until a quarter as many entries as it looked at have been
+    /// appended since.
+    pub fn compress(&mut self, threshold: f64, now: i64) -> anyhow::Result<Option<CompressionReport>> {
+        let budget = (self.capacity as f64 * threshold) as u64;
+        if self.usage <= budget || self.appended < self.compress_after {
+            return Ok(None);
+        }
+        let started = Instant::now();
+
+        let target = budget / 2;
+        // Usage is what loose entries and blocks hold, so the loose tail needs no re-measuring.
+        let mut tail = self.usage.saturating_sub(self.blocks.iter().map(CompressedBlock::size).sum());
+        let mut picked = vec![false; self.entries.len()];
+        let mut packed = Vec::new();
+        // Always leave the newest entry uncompressed.
+        let last = self.entries.len().saturating_sub(1);
+        for (i, e) in self.entries.iter().enumerate().take(last) {
+            if tail <= target {
+                break;
+            }
+            if e.pinned || self.retention.contains_key(&e.kind) {
+                continue;
+            }
+            tail -= e.size();
+            picked[i] = true;
+            packed.push(e.clone());
+        }
+        let looked_at = picked.iter().rposition(|&p| p).map_or(last, |i| i + 1) as u64;
+        let block = if packed.len() < 2 { None } else { Some(CompressedBlock::pack(&packed)?) };
+        let Some(block) = block.filter(|b| b.size() < b.raw_size) else {
+            self.compress_after = self.appended + (looked_at / 4).max(1);
+            return Ok(None);
+        };
+
+        let entries_before = self.len();
+        let size_before = self.usage;
+        self.usage = self.usage.saturating_sub(block.raw_size).saturating_add(block.size());
+        let mut i = 0;
+        self.entries.retain(|_| {
+            i += 1;
+            !picked[i - 1]
+        });
+        let entries_packed = block.entry_count;
+        self.blocks.push_back(block);
+        self.compactions += 1;
+
+        Ok(Some(CompressionReport {
+            started_at: now,
+            entries_before,
+            entries_after: self.len(),
+            entries_packed,
+            size_before,
+            size_after: self.usage,
+            duration_secs: started.elapsed().as_secs_f64(),
+        }))
+    }
+
+    /// Evict entries past their type's retention limits, newest kept first. Entries are only
+    /// walked when the running totals show a limit is exceeded.
+    /// Returns the number evicted.
+    pub fn apply_retention(&mut self, now: i64) -> usize {
+        if !self.retention.values().any(RetentionRule::is_bounded) {
+            return 0;
+        }
+        if self.tally.as_ref().is_none_or(|t| t.policy != self.retention) {
+            self.tally = Some(RetentionTally::build(&self.retention, &self.entries));
+        }
+        if self.tally.as_ref().is_some_and(|t| !t.exceeded(now)) {
+            return 0;
+        }
+
+        let mut kept = RetentionTally { policy: self.retention.clone(), types: BTreeMap::new() };
+        let mut evict = vec![false; self.entries.len()];
+        for (i, e) in self.entries.iter().enumerate().rev() {
+            if e.pinned { continue; }
+            let Some(rule) = self.retention.get(&e.kind).filter(|r| r.is_bounded()) else { continue };
+            let (count, bytes) = kept.types.get(&e.kind).map_or((0, 0), |t| (t.count, t.bytes));
+            let size = e.size();
+            evict[i] = rule.max_age.is_some_and(|age| now.saturating_sub(e.timestamp) > age as i64)
+                || rule.max_count.is_some_and(|max| count >= max)
+                || rule.max_bytes.is_some_and(|max| bytes + size > max);
+            if !evict[i] {
+                kept.types.insert(e.kind, TypeTally { count: count + 1, bytes: bytes + size, oldest: e.timestamp });
+            }
+        }
+        self.tally = Some(kept);
+
+        let mut evicted = 0;
+        let mut i = 0;
+        let mut freed = 0;
+        let mut newest_input = self.input_evicted_upto;
+        self.entries.retain(|e| {
+            i += 1;
+            if !evict[i - 1] {
+                return true;
+            }
+            freed += e.size();
+            evicted += 1;
+            if e.kind == EntryType::INPUT {
+                newest_input = newest_input.max(Some(e.seq));
+            }
+            false
+        });
+        self.usage = self.usage.saturating_sub(freed);
+        self.input_evicted_upto = newest_input;
+        evicted
+    }
+
+    /// Apply retention rules, then evict the oldest memory until both `capacity` and
+    /// `max_entries` are respected. Blocks go whole; pinned entries and types marked
+    /// `never_evict` are skipped, so the latter alone can hold the room over its bounds. Each eviction is a constant-time pop
+    /// unless protected entries sit at the front. Returns the number of entries evicted.
+    pub fn truncate_to_fit(&mut self, now: i64) -> u64 {
+        let before = self.len();
+        self.apply_retention(now);
+        let mut count = self.len();
+        while self.usage > self.capacity || count > self.max_entries as u64 {
+            let loose = self.entries.iter().position(|e| !self.is_protected(e));
+            let block_first = self.blocks.front().map(|b| b.first_seq);
+            let take_block = match (block_first, loose) {
+                (Some(b), Some(i)) => b < self.entries[i].seq,
+                (Some(_), None) => true,
+                (None, _) => false,
+            };
+            let (freed, evicted) = if take_block {
+                let Some(block) = self.blocks.pop_front() else { break };
+                // Packed entries are not inspected, so assume the block held inputs.
+                self.input_evicted_upto = self.input_evicted_upto.max(Some(block.last_seq));
+                (block.size(), block.entry_count)
+            } else if let Some(entry) = loose.and_then(|i| self.entries.remove(i)) {
+                if entry.kind == EntryType::INPUT {
+                    self.input_evicted_upto = self.input_evicted_upto.max(Some(entry.seq));
+                }
+                let size = entry.size();
+                if let Some(tally) = &mut self.tally {
+                    tally.remove(&entry, size);
+                }
+                (size, 1)
+            } else {
+                break;
+            };
+            self.usage = self.usage.saturating_sub(freed);
+            count -= evicted;
+        }
+        before - count
+    }
+
+    fn is_protected(&self, e: &MemoryEntry) -> bool {
+        e.pinned || self.retention.get(&e.kind).is_some_and(|r| r.never_evict)
+    }
+
+    /// Bytes held by pinned entries.
+    pub fn pinned_usage(&self) -> u64 {
+        self.entries.iter().filter(|e| e.pinned).map(MemoryEntry::size).sum()
+    }
+
+    /// Pin or unpin every entry recorded at `timestamp`, pulling pinned ones out of compressed
+    /// blocks. Fails without changing anything if pinning would exceed `pinned_capacity`.
+    /// Returns the number of entries at `timestamp`.
+    pub fn set_pinned(&mut self, timestamp: i64, pinned: bool) -> anyhow::Result<usize> {
+        let cap = self.pinned_capacity.min(self.capacity);
+        if pinned {
+            let mut requested = self.pinned_usage();
+            for e in self.entries.iter().filter(|e| e.timestamp == timestamp && !e.pinned) {
+                requested += e.size() + PINNED_FLAG_SIZE;
+            }
+            for b in self.blocks.iter().filter(|b| (b.first_timestamp..=b.last_timestamp).contains(&timestamp)) {
+                for e in b.unpack()?.iter().filter(|e| e.timestamp == timestamp) {
+                    requested += e.size() + PINNED_FLAG_SIZE;
+                }
+            }
+            if requested > cap {
+                return Err(RoomError::PinnedLimitExceeded { requested, max: cap }.into());
+            }
+            self.unpack_at(timestamp)?;
+        }
+
+        let mut matched = 0;
+        let mut changed = false;
+        for e in self.entries.iter_mut().filter(|e| e.timestamp == timestamp) {
+            matched += 1;
+            if e.pinned != pinned {
+                let before = e.size();
+                e.pinned = pinned;
+                self.usage = (self.usage + e.size()).saturating_sub(before);
+                changed = true;
+            }
+        }
+        if changed {
+            self.revision += 1;
+            self.tally = None;
+        }
+        Ok(matched)
+    }
+
+    /// Rewrite held entries in place, compressed ones included; `f` returns true when it
+    /// changed an entry. Keeps `usage` in step and bumps `revision` so indexes rebuild.
+    /// Returns the number of entries changed.
+    pub fn rewrite(&mut self, mut f: impl FnMut(&mut MemoryEntry) -> bool) -> anyhow::Result<usize> {
+        let mut changed = 0;
+        for e in self.entries.iter_mut() {
+            let before = e.size();
+            if f(e) {
+                changed += 1;
+                self.usage = (self.usage + e.size()).saturating_sub(before);
+            }
+        }
+        for b in self.blocks.iter_mut() {
+            let mut packed = b.unpack()?;
+            let hits = packed.iter_mut().fold(0, |n, e| n + f(e) as usize);
+            if hits > 0 {
+                let repacked = CompressedBlock::pack(&packed)?;
+                self.usage = (self.usage + repacked.size()).saturating_sub(b.size());
+                *b = repacked;
+                changed += hits;
+            }
+        }
+        if changed > 0 {
+            self.revision += 1;
+            self.tally = None;
+        }
+        Ok(changed)
+    }
+
+    /// Move entries recorded at `timestamp` out of compressed blocks into `entries`.
+    fn unpack_at(&mut self, timestamp: i64) -> anyhow::Result<()> {
+        let mut kept = VecDeque::new();
+        for b in std::mem::take(&mut self.blocks) {
+            if !(b.first_timestamp..=b.last_timestamp).contains(&timestamp) {
+                kept.push_back(b);
+                continue;
+            }
+            let (hits, rest): (Vec<MemoryEntry>, Vec<MemoryEntry>) =
+                b.unpack()?.into_iter().partition(|e| e.timestamp == timestamp);
+            if hits.is_empty() {
+                kept.push_back(b);
+                continue;
+            }
+            self.usage = self.usage.saturating_sub(b.size());
+            if !rest.is_empty() {
+                let repacked = CompressedBlock::pack(&rest)?;
+                self.usage += repacked.size();
+                kept.push_back(repacked);
+            }
+            for e in hits {
+                self.usage += e.size();
+                let at = self.entries.partition_point(|x| x.seq < e.seq);
+                self.entries.insert(at, e);
+            }
+            self.compactions += 1;
+        }
+        self.blocks = kept;
+        Ok(())
+    }
+
+    /// Usage derived from the entries themselves, independent of the stored `usage` field.
+    pub fn computed_usage(&self) -> u64 {
+        self.entries.iter().map(MemoryEntry::size).sum::<u64>()
+            + self.blocks.iter().map(CompressedBlock::size).sum::<u64>()
     }
 
-    pub fn append(&mut self, entry: MemoryEntry) {
-        self.usage = self.usage.saturating_add(entry.content.len() as u64);
-        self.entries.push(entry);
+    pub fn count_of(&self, kind: EntryType) -> u64 {
+        self.entries.iter().filter(|e| e.kind == kind).count() as u64
     }
 
-    pub fn truncate_to_fit(&mut self) {
-        while self.usage > self.capacity && !self.entries.is_empty() {
-            let removed = self.entries.remove(0);
-            self.usage = self.usage.saturating_sub(removed.content.len() as u64);
+    /// Entry count and bytes per type among uncompressed entries. Compressed blocks mix types
+    /// and are reported separately.
+    pub fn usage_by_type(&self) -> BTreeMap<EntryType, (u64, u64)> {
+        let mut out: BTreeMap<EntryType, (u64, u64)> = BTreeMap::new();
+        for e in &self.entries {
+            let (count, bytes) = out.entry(e.kind).or_default();
+            *count += 1;
+            *bytes += e.size();
         }
+        out
     }
 }
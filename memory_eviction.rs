@@ -0,0 +1,40 @@
+use backrooms_terminal::memory::{EntryType, MemoryEntry, MemoryStore};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+
+fn filled(n: u64) -> MemoryStore {
+    let mut m = MemoryStore::new(u64::MAX);
+    for i in 0..n {
+        m.append(MemoryEntry{
+            seq: 0,
+            timestamp: i as i64,
+            kind: EntryType::INPUT,
+            content: format!("remember key{}: value", i),
+            metadata: serde_json::json!({}),
+            pinned: false,
+        });
+    }
+    m
+}
+
+/// Evicting half of a full store should cost the same per entry regardless of store size.
+fn evict_half(c: &mut Criterion) {
+    let mut group = c.benchmark_group("truncate_to_fit");
+    for n in [1_000u64, 10_000, 100_000] {
+        group.throughput(Throughput::Elements(n / 2));
+        group.bench_with_input(BenchmarkId::from_parameter(n), &n, |b, &n| {
+            b.iter_batched(
+                || {
+                    let mut m = filled(n);
+                    m.capacity = m.usage / 2;
+                    m
+                },
+                |mut m| m.truncate_to_fit(0),
+                BatchSize::LargeInput,
+            );
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, evict_half);
+criterion_main!(benches);
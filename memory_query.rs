@@ -0,0 +1,96 @@
+mod common;
+
+use backrooms_terminal::memory::{EntryType, MemoryEntry, RetentionRule};
+use backrooms_terminal::persistence::sqlite::SqlitePersistence;
+use backrooms_terminal::persistence::Persistence;
+use backrooms_terminal::query::{MemoryQuery, MetaPredicate};
+use backrooms_terminal::room::RoomConfig;
+use tempfile::tempdir;
+
+#[test]
+fn sqlite_query_matches_in_memory_query() {
+    let dir = tempdir().unwrap();
+    let p = SqlitePersistence::new(dir.path().join("rooms.db"));
+    p.init().unwrap();
+
+    let mut room = common::room("q", RoomConfig::default(), 0);
+    for i in 0..20 {
+        room.memory.append(MemoryEntry{
+            seq: 0,
+            timestamp: i,
+            kind: if i % 2 == 0 { EntryType::INPUT } else { EntryType::OUTPUT },
+            content: format!("line {} {}", i, if i % 3 == 0 { "fizz" } else { "" }),
+            metadata: serde_json::json!({"n": i, "tag": {"even": i % 2 == 0}}),
+            pinned: false,
+        });
+    }
+    p.save_room(&room).unwrap();
+
+    let queries = vec![
+        MemoryQuery{ kinds: vec![EntryType::INPUT], since: Some(4), until: Some(15), ..Default::default() },
+        MemoryQuery{ contains: Some("fizz".to_string()), reverse: true, limit: Some(3), offset: 1, ..Default::default() },
+        MemoryQuery{ regex: Some(regex::Regex::new(r"line 1\d").unwrap()), ..Default::default() },
+        MemoryQuery{ metadata: vec![MetaPredicate::parse("$.tag.even=false").unwrap()], limit: Some(2), ..Default::default() },
+    ];
+    for q in &queries {
+        let sql: Vec<i64> = p.query_memory("q", q).unwrap().iter().map(|e| e.timestamp).collect();
+        let mem: Vec<i64> = room.memory.query(q).unwrap().iter().map(|e| e.timestamp).collect();
+        assert!(!mem.is_empty());
+        assert_eq!(sql, mem);
+    }
+
+    // Retention evicts OUTPUT entries from the middle of the log; the mirror must follow.
+    room.memory.retention.insert(EntryType::OUTPUT, RetentionRule{ max_count: Some(3), ..Default::default() });
+    room.memory.truncate_to_fit(20);
+    p.save_room(&room).unwrap();
+    for q in &queries[1..] {
+        let sql: Vec<i64> = p.query_memory("q", q).unwrap().iter().map(|e| e.timestamp).collect();
+        let mem: Vec<i64> = room.memory.query(q).unwrap().iter().map(|e| e.timestamp).collect();
+        assert_eq!(sql, mem);
+    }
+}
+
+#[test]
+fn full_text_index_follows_eviction() {
+    use backrooms_terminal::persistence::filesystem::FilesystemPersistence;
+
+    let dir = tempdir().unwrap();
+    let backends: Vec<Box<dyn Persistence>> = vec![
+        Box::new(SqlitePersistence::new(dir.path().join("rooms.db"))),
+        Box::new(FilesystemPersistence::new(dir.path().join("rooms"))),
+    ];
+    for p in backends {
+        p.init().unwrap();
+        let mut room = common::room("fts", RoomConfig::default(), 0);
+        for i in 0..10 {
+            room.memory.append(MemoryEntry{
+                seq: 0,
+                timestamp: i,
+                kind: EntryType::INPUT,
+                content: format!("remember reactor {}: {}", i, if i % 2 == 0 { "stable" } else { "venting" }),
+                metadata: serde_json::json!({}),
+                pinned: false,
+            });
+        }
+        p.save_room(&room).unwrap();
+        let hits = p.search_memory("fts", "reactor venting", 10).unwrap();
+        assert_eq!(hits.len(), 5);
+        assert!(hits[0].snippet.contains("[venting]"));
+
+        room.memory.max_entries = 4;
+        room.memory.truncate_to_fit(0);
+        room.memory.append(MemoryEntry{
+            seq: 0,
+            timestamp: 10,
+            kind: EntryType::OUTPUT,
+            content: "reactor venting again".to_string(),
+            metadata: serde_json::json!({}),
+            pinned: false,
+        });
+        p.save_room(&room).unwrap();
+
+        let mut ts: Vec<i64> = p.search_memory("fts", "Reactor VENTING", 10).unwrap().iter().map(|h| h.timestamp).collect();
+        ts.sort();
+        assert_eq!(ts, vec![7, 9, 10]);
+    }
+}
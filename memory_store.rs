@@ -0,0 +1,204 @@
+mod common;
+
+use backrooms_terminal::memory::{check_compression, EntryType, MemoryEntry, MemoryStore, RetentionRule};
+
+fn entry(ts: i64, content: &str) -> MemoryEntry {
+    MemoryEntry{
+        seq: 0,
+        timestamp: ts,
+        kind: EntryType::INPUT,
+        content: content.to_string(),
+        metadata: serde_json::json!({}),
+        pinned: false,
+    }
+}
+
+#[test]
+fn compression_packs_old_entries_before_truncating() {
+    let mut m = MemoryStore::new(4096);
+    for i in 0..80 {
+        m.append(entry(i, &"remember key: repetitive value ".repeat(2)));
+    }
+    assert!(m.usage > 4096);
+
+    let report = m.compress(0.85, 100).unwrap().expect("compression should run");
+    assert_eq!(report.entries_before, 80);
+    assert_eq!(report.entries_after, 80);
+    assert!(report.size_after < report.size_before);
+    assert_eq!(m.usage, m.computed_usage());
+    assert!(m.usage <= m.capacity);
+
+    m.truncate_to_fit(0);
+    assert_eq!(m.len(), 80);
+    let all = m.all_entries().unwrap();
+    assert_eq!(all.len(), 80);
+    assert_eq!(all[0].timestamp, 0);
+    assert_eq!(all[79].timestamp, 79);
+}
+
+#[test]
+fn compression_skipped_under_threshold() {
+    let mut m = MemoryStore::new(4096);
+    m.append(entry(0, "small"));
+    assert!(m.compress(0.85, 1).unwrap().is_none());
+}
+
+#[test]
+fn compression_backs_off_after_a_pass_that_saves_nothing() {
+    let mut m = MemoryStore::new(4096);
+    for i in 0..60 {
+        m.append(MemoryEntry { pinned: true, ..entry(i, &"x".repeat(60)) });
+    }
+    assert!(m.compress(0.85, 100).unwrap().is_none());
+    let resume = m.compress_after;
+    assert!(resume > m.appended);
+
+    while m.appended + 1 < resume {
+        m.append(entry(m.appended as i64, &"remember key: repetitive value ".repeat(2)));
+        assert!(m.compress(0.85, 100).unwrap().is_none());
+    }
+    m.append(entry(m.appended as i64, &"remember key: repetitive value ".repeat(2)));
+    assert!(m.compress(0.85, 100).unwrap().is_some());
+    assert_eq!(m.usage, m.computed_usage());
+}
+
+#[test]
+fn only_deflate_or_none_is_accepted() {
+    assert!(check_compression("deflate").is_ok());
+    assert!(check_compression("none").is_ok());
+    assert_eq!(check_compression("zstd").unwrap_err().to_string(), "ERROR: UNSUPPORTED_COMPRESSION (zstd; supported: deflate, none)");
+}
+
+#[test]
+fn usage_counts_metadata_and_entries_are_bounded() {
+    let mut m = MemoryStore::new(u64::MAX).with_max_entries(3);
+    let mut e = entry(0, "x");
+    let bare = e.size();
+    e.metadata = serde_json::json!({"key": "a fairly long metadata value"});
+    assert!(e.size() > bare);
+
+    for i in 0..5 {
+        m.append(entry(i, "x"));
+    }
+    m.truncate_to_fit(0);
+    assert_eq!(m.len(), 3);
+    assert_eq!(m.entries.front().unwrap().timestamp, 2);
+    assert_eq!(m.usage, m.computed_usage());
+}
+
+#[test]
+fn retention_rules_evict_per_type() {
+    let mut m = MemoryStore::new(u64::MAX);
+    m.retention.insert(EntryType::OBSERVATION, RetentionRule{ max_count: Some(2), ..Default::default() });
+    m.retention.insert(EntryType::ERROR, RetentionRule{ max_age: Some(10), ..Default::default() });
+    for i in 0..5 {
+        m.append(MemoryEntry{ kind: EntryType::OBSERVATION, ..entry(i, "noise") });
+        m.append(MemoryEntry{ kind: EntryType::ERROR, ..entry(i * 10, "failure") });
+        m.append(entry(i, "input"));
+    }
+
+    m.truncate_to_fit(45);
+    let all = m.all_entries().unwrap();
+    let observations: Vec<u64> = all.iter().filter(|e| e.kind == EntryType::OBSERVATION).map(|e| e.seq).collect();
+    assert_eq!(observations, vec![9, 12]);
+    let errors: Vec<i64> = all.iter().filter(|e| e.kind == EntryType::ERROR).map(|e| e.timestamp).collect();
+    assert_eq!(errors, vec![40]);
+    assert_eq!(m.count_of(EntryType::INPUT), 5);
+    assert_eq!(m.usage, m.computed_usage());
+    assert_eq!(m.next_seq(), 15);
+}
+
+#[test]
+fn retention_keeps_up_across_inputs_and_policy_changes() {
+    let mut m = MemoryStore::new(u64::MAX);
+    m.retention.insert(EntryType::OBSERVATION, RetentionRule{ max_count: Some(3), max_age: Some(100), ..Default::default() });
+    for i in 0..10 {
+        m.append(MemoryEntry{ kind: EntryType::OBSERVATION, ..entry(i, "noise") });
+        m.truncate_to_fit(i);
+        assert!(m.count_of(EntryType::OBSERVATION) <= 3);
+    }
+    assert_eq!(m.apply_retention(10), 0);
+    assert_eq!(m.apply_retention(108), 1);
+
+    m.set_pinned(9, true).unwrap();
+    m.retention.insert(EntryType::OBSERVATION, RetentionRule{ max_count: Some(1), ..Default::default() });
+    assert_eq!(m.apply_retention(108), 0);
+    assert_eq!(m.count_of(EntryType::OBSERVATION), 2);
+    m.set_pinned(9, false).unwrap();
+    assert_eq!(m.apply_retention(108), 1);
+    assert_eq!(m.all_entries().unwrap().iter().map(|e| e.timestamp).collect::<Vec<_>>(), vec![9]);
+    assert_eq!(m.usage, m.computed_usage());
+}
+
+#[test]
+fn never_evict_survives_pressure_and_compression() {
+    let mut m = MemoryStore::new(4096);
+    m.retention.insert(EntryType::STATE_CHANGE, RetentionRule{ never_evict: true, ..Default::default() });
+    m.append(MemoryEntry{ kind: EntryType::STATE_CHANGE, ..entry(0, "state: ACTIVE") });
+    for i in 1..200 {
+        m.append(entry(i, &"remember key: repetitive value ".repeat(2)));
+    }
+
+    m.compress(0.85, 200).unwrap().expect("compression should run");
+    assert_eq!(m.entries.front().unwrap().kind, EntryType::STATE_CHANGE);
+    m.capacity = 512;
+    m.truncate_to_fit(200);
+    assert!(m.blocks.is_empty());
+    let all = m.all_entries().unwrap();
+    assert_eq!(all[0].content, "state: ACTIVE");
+    assert_eq!(all[0].seq, 0);
+    assert_eq!(m.usage_by_type()[&EntryType::STATE_CHANGE].0, 1);
+    assert!(m.input_evicted_upto.is_some());
+}
+
+#[test]
+fn pinned_entries_survive_and_are_capped() {
+    let mut m = MemoryStore::new(4096).with_pinned_capacity(300);
+    for i in 0..100 {
+        m.append(entry(i, &"remember key: repetitive value ".repeat(2)));
+    }
+    assert_eq!(m.set_pinned(1, true).unwrap(), 1);
+    m.compress(0.85, 100).unwrap().expect("compression should run");
+    assert!(m.entries.iter().any(|e| e.pinned && e.timestamp == 1));
+
+    // Pinning an entry already packed pulls it back out of its block.
+    let packed_ts = m.blocks[0].last_timestamp;
+    assert_eq!(m.set_pinned(packed_ts, true).unwrap(), 1);
+    assert!(m.entries.iter().any(|e| e.pinned && e.timestamp == packed_ts));
+    assert_eq!(m.usage, m.computed_usage());
+    assert_eq!(m.len(), 100);
+
+    let err = m.set_pinned(99, true).unwrap_err();
+    assert!(err.to_string().starts_with("ERROR: PINNED_LIMIT_EXCEEDED"));
+
+    m.capacity = m.pinned_usage();
+    m.truncate_to_fit(100);
+    let left: Vec<i64> = m.all_entries().unwrap().iter().map(|e| e.timestamp).collect();
+    assert_eq!(left, vec![1, packed_ts]);
+
+    assert_eq!(m.set_pinned(1, false).unwrap(), 1);
+    m.capacity = m.pinned_usage();
+    m.truncate_to_fit(100);
+    assert_eq!(m.len(), 1);
+    assert_eq!(m.usage, m.computed_usage());
+}
+
+#[test]
+fn compression_report_goes_to_the_session_output() {
+    use backrooms_terminal::config::Config;
+    use backrooms_terminal::session;
+
+    let cfg = Config::load(None).unwrap();
+    let mut rc = session::room_config_from(&cfg);
+    rc.memory_limit = 4096;
+    rc.compression = "deflate".to_string();
+    let mut room = common::room("c", rc, 0);
+
+    let mut out: Vec<String> = Vec::new();
+    for i in 0..80 {
+        session::stream_input(&mut room, &format!("remember k{}: repetitive value repetitive value", i), i, &cfg, &mut out);
+    }
+    assert!(out.iter().any(|l| l.starts_with("COMPRESSION_STARTED: ")));
+    assert!(out.iter().any(|l| l.starts_with("COMPRESSION_RATIO: ")));
+    assert!(room.memory.entries_from(0).unwrap().iter().any(|e| e.content.starts_with("COMPRESSION: ")));
+}
@@ -1,8 +1,13 @@
+use crate::memory::MemoryEntry;
+use crate::query::{self, MemoryQuery, SearchHit};
 use crate::room::{Room, RoomState};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 pub mod filesystem;
+pub mod fsck;
 pub mod sqlite;
+pub mod text_index;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RoomSummary {
@@ -22,4 +27,21 @@ pub trait Persistence: Send + Sync {
     fn load_room(&self, id: &str) -> anyhow::Result<Room>;
     fn save_room(&self, room: &Room) -> anyhow::Result<()>;
     fn delete_room(&self, id: &str) -> anyhow::Result<()>;
+    /// Walk every stored room and verify it; with `repair`, rewrite recomputable fields.
+    fn fsck(&self, repair: bool) -> anyhow::Result<fsck::FsckReport>;
+    /// Search one room's memory. Backends override this to push filters into storage.
+    fn query_memory(&self, id: &str, q: &MemoryQuery) -> anyhow::Result<Vec<MemoryEntry>> {
+        self.load_room(id)?.memory.query(q)
+    }
+    /// Ranked full-text search over one room's memory. The default scans every entry.
+    fn search_memory(&self, id: &str, text: &str, limit: usize) -> anyhow::Result<Vec<SearchHit>> {
+        let memory = self.load_room(id)?.memory;
+        let entries = memory.all_entries()?;
+        Ok(query::rank(entries.iter().map(|e| (e.seq, e)), &query::tokenize(text), limit))
+    }
+}
+
+/// Hex SHA-256 of a serialized room, stored next to it so corruption can be detected.
+pub fn checksum(raw: &str) -> String {
+    hex::encode(Sha256::digest(raw.as_bytes()))
 }
@@ -0,0 +1,312 @@
+use crate::config::{EntityConfig, ProcessEntityConfig};
+use crate::entity::{self, record_mutation, write_counter, Entity, Output, OutputLevel, OutputSink};
+use crate::error::RoomError;
+use crate::memory::{EntryType, MemoryEntry};
+use crate::room::{Room, RoomState};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Messages room.exe writes to an entity process, one JSON object per line.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request<'a> {
+    /// One line of input, with the room's kv pairs and counters as they stand.
+    Input {
+        room_id: &'a str,
+        input: &'a str,
+        now: i64,
+        state: RoomState,
+        kv: &'a HashMap<String, String>,
+        counters: &'a HashMap<String, i64>,
+    },
+}
+
+/// Messages an entity process writes back. Any number of `output` lines are followed by one
+/// `done`, which ends the input.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Reply {
+    /// A line to emit. Without a level it is leveled like any plain line.
+    Output {
+        text: String,
+        #[serde(default)]
+        level: Option<OutputLevel>,
+        #[serde(default)]
+        payload: Option<serde_json::Value>,
+    },
+    /// The input is handled. Snapshots given here replace the room's kv pairs or counters.
+    Done {
+        #[serde(default)]
+        kv: Option<HashMap<String, String>>,
+        #[serde(default)]
+        counters: Option<HashMap<String, i64>>,
+    },
+}
+
+/// A running entity process. The pipes and the process sit behind separate locks so the
+/// session can kill a child while an invocation is blocked reading from it.
+struct Worker {
+    process: Mutex<Child>,
+    pipes: Mutex<(ChildStdin, BufReader<ChildStdout>)>,
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        let process = self.process.get_mut();
+        kill(process);
+        let _ = process.wait();
+    }
+}
+
+/// Kill a child and whatever it started: on Unix the child leads its own process group, and
+/// the whole group is killed.
+fn kill(process: &mut Child) {
+    #[cfg(unix)]
+    // SAFETY: killpg only sends a signal. The group was created for this child at spawn.
+    unsafe {
+        libc::killpg(process.id() as libc::pid_t, libc::SIGKILL);
+    }
+    let _ = process.kill();
+}
+
+/// Entity kind served by an external executable, one child per room. The child is spawned on
+/// the room's first input and killed on timeout, crash, suspension or destruction; the next
+/// input starts a fresh one. Room state lives in the room as for any entity: the child gets
+/// a snapshot with every input and may hand back a new one.
+pub struct ProcessEntity {
+    name: String,
+    config: ProcessEntityConfig,
+    workers: Mutex<HashMap<String, Arc<Worker>>>,
+}
+
+impl ProcessEntity {
+    /// Version of the JSON line protocol.
+    pub const VERSION: &'static str = "1.0.0";
+
+    pub fn new(name: impl Into<String>, config: ProcessEntityConfig) -> Self {
+        Self { name: name.into(), config, workers: Mutex::new(HashMap::new()) }
+    }
+
+    /// The room's child, started on first use. The lock is held across the spawn, so two
+    /// inputs racing into a new room cannot both start one.
+    fn worker(&self, room: &Room) -> io::Result<Arc<Worker>> {
+        match self.workers.lock().entry(room.id.clone()) {
+            Entry::Occupied(e) => Ok(e.get().clone()),
+            Entry::Vacant(e) => Ok(e.insert(Arc::new(self.spawn(room)?)).clone()),
+        }
+    }
+
+    fn spawn(&self, room: &Room) -> io::Result<Worker> {
+        let (program, args) = self.config.command.split_first()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "empty command"))?;
+        let mut cmd = Command::new(program);
+        cmd.args(args)
+            .env("ROOM_ID", &room.id)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null());
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            cmd.process_group(0);
+            let limits = [
+                (libc::RLIMIT_CPU, self.config.cpu_seconds),
+                (libc::RLIMIT_AS, self.config.address_space),
+                (libc::RLIMIT_NOFILE, self.config.open_files),
+            ];
+            // SAFETY: the closure runs in the forked child before exec and only calls
+            // setrlimit, which is async-signal-safe.
+            unsafe {
+                cmd.pre_exec(move || {
+                    for (resource, value) in limits {
+                        if value == 0 {
+                            continue;
+                        }
+                        let limit = libc::rlimit { rlim_cur: value as libc::rlim_t, rlim_max: value as libc::rlim_t };
+                        if libc::setrlimit(resource, &limit) != 0 {
+                            return Err(io::Error::last_os_error());
+                        }
+                    }
+                    Ok(())
+                });
+            }
+        }
+        let mut child = cmd.spawn()?;
+        let stdin = child.stdin.take().expect("piped stdin");
+        let stdout = BufReader::new(child.stdout.take().expect("piped stdout"));
+        Ok(Worker { process: Mutex::new(child), pipes: Mutex::new((stdin, stdout)) })
+    }
+
+    /// Kill the room's child and its process group, if it has one.
+    fn stop(&self, room_id: &str) {
+        if let Some(worker) = self.workers.lock().remove(room_id) {
+            kill(&mut worker.process.lock());
+        }
+    }
+
+    /// Send one input and relay replies until `done`. Errors mean the child is unusable.
+    fn exchange(&self, worker: &Worker, room: &mut Room, input: &str, now: i64, out: &mut dyn OutputSink) -> Result<(), String> {
+        let request = Request::Input {
+            room_id: &room.id,
+            input,
+            now,
+            state: room.state,
+            kv: &room.entity_state.kv,
+            counters: &room.entity_state.counters,
+        };
+        let mut line = serde_json::to_string(&request).map_err(|e| e.to_string())?;
+        line.push('\n');
+        let mut pipes = worker.pipes.lock();
+        let (stdin, stdout) = &mut *pipes;
+        if let Err(e) = stdin.write_all(line.as_bytes()).and_then(|_| stdin.flush()) {
+            return Err(self.exited(worker).unwrap_or_else(|| format!("cannot write to entity process: {}", e)));
+        }
+        loop {
+            let mut line = String::new();
+            match stdout.read_line(&mut line) {
+                Ok(0) => return Err(self.exited(worker).unwrap_or_else(|| "entity process closed its output".to_string())),
+                Ok(_) => {}
+                Err(e) => return Err(format!("cannot read from entity process: {}", e)),
+            }
+            let reply: Reply = serde_json::from_str(&line)
+                .map_err(|e| format!("invalid message from entity process: {}", e))?;
+            match reply {
+                Reply::Output { text, level, payload } => {
+                    let mut output = match level {
+                        Some(level) => Output::new(level, text),
+                        None => Output::line(&text),
+                    };
+                    output.payload = payload;
+                    out.send(output);
+                }
+                Reply::Done { kv, counters } => {
+                    if let Some(kv) = kv {
+                        replace_kv(room, now, kv);
+                    }
+                    if let Some(counters) = counters {
+                        replace_counters(room, now, counters);
+                    }
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// How the child ended, if it has. A child closes its pipes a moment before it can be
+    /// reaped, so this waits briefly rather than blocking on a child that only closed them.
+    fn exited(&self, worker: &Worker) -> Option<String> {
+        let mut process = worker.process.lock();
+        for _ in 0..20 {
+            if let Ok(Some(status)) = process.try_wait() {
+                return Some(format!("entity process exited with {}", status));
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        None
+    }
+}
+
+impl Entity for ProcessEntity {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn version(&self) -> &str {
+        Self::VERSION
+    }
+
+    fn handle_input(&self, room: &mut Room, input: &str, now: i64) -> Option<String> {
+        let mut lines: Vec<String> = Vec::new();
+        if let Err(e) = self.stream_input(room, input, now, &mut lines) {
+            lines.push(e.to_string());
+        }
+        (!lines.is_empty()).then(|| lines.join("\n"))
+    }
+
+    /// A child that cannot be started, exits or breaks the protocol is reported as a crash:
+    /// the session discards the input and calls `restart`, and the next input spawns a new
+    /// child.
+    fn stream_input(&self, room: &mut Room, input: &str, now: i64, out: &mut dyn OutputSink) -> Result<(), RoomError> {
+        room.memory.append(MemoryEntry {
+            seq: 0,
+            timestamp: now,
+            kind: EntryType::INPUT,
+            content: input.to_string(),
+            metadata: serde_json::json!({}),
+            pinned: false,
+        });
+        let result = match self.worker(room) {
+            Ok(worker) => self.exchange(&worker, room, input, now, out),
+            Err(e) => Err(format!("cannot start entity process: {}", e)),
+        };
+        result.map_err(|message| RoomError::EntityCrashed { message })
+    }
+
+    /// Inputs went to a child that may have acted on the world; replaying them would repeat
+    /// that.
+    fn replayable(&self) -> bool {
+        false
+    }
+
+    fn on_state_change(&self, room: &mut Room, _from: RoomState, to: RoomState, _now: i64) {
+        if matches!(to, RoomState::SUSPENDED | RoomState::TERMINATED) {
+            self.stop(&room.id);
+        }
+    }
+
+    fn abort(&self, room_id: &str) {
+        self.stop(room_id);
+    }
+
+    fn shutdown(&self, room: &mut Room, _now: i64) {
+        self.stop(&room.id);
+    }
+
+    fn restart(&self, room: &mut Room, _now: i64) {
+        self.stop(&room.id);
+    }
+}
+
+/// Register an entity kind for every executable in `entity.processes`.
+pub fn register_configured(cfg: &EntityConfig) {
+    for (name, config) in &cfg.processes {
+        entity::register(Arc::new(ProcessEntity::new(name.clone(), config.clone())));
+    }
+}
+
+fn replace_kv(room: &mut Room, now: i64, kv: HashMap<String, String>) {
+    let mut removed: Vec<String> = room.entity_state.kv.keys().filter(|k| !kv.contains_key(*k)).cloned().collect();
+    removed.sort();
+    for key in removed {
+        let old = room.entity_state.kv.remove(&key);
+        record_mutation(room, now, "kv_delete", &key, serde_json::json!(old), serde_json::Value::Null);
+    }
+    let mut changed: Vec<(String, String)> = kv.into_iter().filter(|(k, v)| room.entity_state.kv.get(k) != Some(v)).collect();
+    changed.sort();
+    for (key, value) in changed {
+        let old = room.entity_state.kv.insert(key.clone(), value.clone());
+        record_mutation(room, now, "kv_set", &key, serde_json::json!(old), serde_json::json!(value));
+    }
+}
+
+fn replace_counters(room: &mut Room, now: i64, counters: HashMap<String, i64>) {
+    let mut removed: Vec<String> = room.entity_state.counters.keys().filter(|k| !counters.contains_key(*k)).cloned().collect();
+    removed.sort();
+    for name in removed {
+        let old = room.entity_state.counters.remove(&name);
+        room.entity_state.counter_history.remove(&name);
+        record_mutation(room, now, "counter_delete", &name, serde_json::json!(old), serde_json::Value::Null);
+    }
+    let mut changed: Vec<(String, i64)> = counters.into_iter().filter(|(k, v)| room.entity_state.counters.get(k) != Some(v)).collect();
+    changed.sort();
+    for (name, value) in changed {
+        write_counter(room, now, "counter_set", &name, value);
+    }
+}
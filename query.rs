@@ -0,0 +1,168 @@
+use crate::memory::{EntryType, MemoryEntry};
+use regex::Regex;
+use serde::Serialize;
+use serde_json::Value;
+
+/// Filter over a room's memory. Every set field must match; pagination applies after filtering.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryQuery {
+    pub kinds: Vec<EntryType>,
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    pub contains: Option<String>,
+    pub regex: Option<Regex>,
+    pub metadata: Vec<MetaPredicate>,
+    pub limit: Option<usize>,
+    pub offset: usize,
+    /// Newest first.
+    pub reverse: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum MetaOp {
+    Exists,
+    Eq(Value),
+    Ne(Value),
+}
+
+/// A predicate on one value inside `MemoryEntry.metadata`.
+#[derive(Debug, Clone)]
+pub struct MetaPredicate {
+    /// JSON pointer, e.g. `/key/0`.
+    pub pointer: String,
+    pub op: MetaOp,
+}
+
+pub fn parse_entry_type(s: &str) -> anyhow::Result<EntryType> {
+    serde_json::from_value(Value::String(s.to_uppercase()))
+        .map_err(|_| anyhow::anyhow!("unknown entry type: {}", s))
+}
+
+impl MetaPredicate {
+    /// Parse `path`, `path=value` or `path!=value`. Paths are dotted (`$.a.b`, `a.0`);
+    /// values are read as JSON when they parse, otherwise as plain strings.
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        let (path, op) = if let Some((p, v)) = s.split_once("!=") {
+            (p, MetaOp::Ne(parse_value(v)))
+        } else if let Some((p, v)) = s.split_once('=') {
+            (p, MetaOp::Eq(parse_value(v)))
+        } else {
+            (s, MetaOp::Exists)
+        };
+        let path = path.trim();
+        let path = path.strip_prefix("$.").or_else(|| path.strip_prefix('$')).unwrap_or(path);
+        if path.is_empty() {
+            anyhow::bail!("empty metadata path in predicate: {}", s);
+        }
+        let pointer = path.split('.').map(|seg| format!("/{}", seg.replace('~', "~0").replace('/', "~1"))).collect();
+        Ok(Self { pointer, op })
+    }
+
+    pub fn matches(&self, metadata: &Value) -> bool {
+        let found = metadata.pointer(&self.pointer);
+        match &self.op {
+            MetaOp::Exists => found.is_some(),
+            MetaOp::Eq(v) => found == Some(v),
+            MetaOp::Ne(v) => found != Some(v),
+        }
+    }
+}
+
+fn parse_value(raw: &str) -> Value {
+    let raw = raw.trim();
+    serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string()))
+}
+
+impl MemoryQuery {
+    pub fn matches(&self, e: &MemoryEntry) -> bool {
+        (self.kinds.is_empty() || self.kinds.contains(&e.kind))
+            && self.since.is_none_or(|t| e.timestamp >= t)
+            && self.until.is_none_or(|t| e.timestamp <= t)
+            && self.contains.as_deref().is_none_or(|s| e.content.contains(s))
+            && self.regex.as_ref().is_none_or(|r| r.is_match(&e.content))
+            && self.metadata.iter().all(|p| p.matches(&e.metadata))
+    }
+
+    /// True when some predicates can only be evaluated in Rust, not by a storage backend.
+    pub fn needs_post_filter(&self) -> bool {
+        self.regex.is_some() || !self.metadata.is_empty()
+    }
+
+    /// Filter, order and paginate entries given oldest first.
+    pub fn apply(&self, entries: Vec<MemoryEntry>) -> Vec<MemoryEntry> {
+        let mut hits: Vec<MemoryEntry> = entries.into_iter().filter(|e| self.matches(e)).collect();
+        if self.reverse {
+            hits.reverse();
+        }
+        self.page(hits)
+    }
+
+    /// Apply offset and limit to already filtered, already ordered hits.
+    pub fn page(&self, hits: Vec<MemoryEntry>) -> Vec<MemoryEntry> {
+        hits.into_iter().skip(self.offset).take(self.limit.unwrap_or(usize::MAX)).collect()
+    }
+}
+
+/// One ranked full-text match. Higher `score` ranks first.
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchHit {
+    pub seq: u64,
+    pub timestamp: i64,
+    pub kind: EntryType,
+    pub snippet: String,
+    pub score: f64,
+}
+
+/// Lowercased alphanumeric terms, the unit both the SQLite and filesystem indexes work in.
+pub fn tokenize(s: &str) -> Vec<String> {
+    s.split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_lowercase())
+        .collect()
+}
+
+const SNIPPET_WORDS: usize = 10;
+
+/// A short window of `content` around the first matching term, with matches in brackets.
+pub fn snippet(content: &str, terms: &[String]) -> String {
+    let words: Vec<&str> = content.split_whitespace().collect();
+    let hit = |w: &str| tokenize(w).iter().any(|t| terms.contains(t));
+    let first = words.iter().position(|w| hit(w)).unwrap_or(0);
+    let start = first.saturating_sub(SNIPPET_WORDS / 2);
+    let end = (start + SNIPPET_WORDS).min(words.len());
+    let mut out: Vec<String> = words[start..end]
+        .iter()
+        .map(|w| if hit(w) { format!("[{}]", w) } else { w.to_string() })
+        .collect();
+    if start > 0 {
+        out.insert(0, "...".to_string());
+    }
+    if end < words.len() {
+        out.push("...".to_string());
+    }
+    out.join(" ")
+}
+
+/// Rank entries containing every term by term frequency, most recent first on ties.
+pub fn rank<'a>(entries: impl Iterator<Item = (u64, &'a MemoryEntry)>, terms: &[String], limit: usize) -> Vec<SearchHit> {
+    if terms.is_empty() {
+        return vec![];
+    }
+    let mut hits: Vec<SearchHit> = entries
+        .filter_map(|(seq, e)| {
+            let tokens = tokenize(&e.content);
+            let mut score = 0.0;
+            for t in terms {
+                let tf = tokens.iter().filter(|x| *x == t).count();
+                if tf == 0 {
+                    return None;
+                }
+                score += tf as f64 / tokens.len() as f64;
+            }
+            Some(SearchHit { seq, timestamp: e.timestamp, kind: e.kind, snippet: snippet(&e.content, terms), score })
+        })
+        .collect();
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score).then(b.seq.cmp(&a.seq)));
+    hits.truncate(limit);
+    hits
+}
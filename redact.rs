@@ -0,0 +1,180 @@
+use crate::entity::EntityState;
+use crate::memory::{EntryType, MemoryEntry};
+use crate::room::Room;
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+pub const DEFAULT_REPLACEMENT: &str = "[REDACTED]";
+
+/// An ingest-time rule from config, applied to every input before it is recorded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedactionRule {
+    pub name: String,
+    #[serde(serialize_with = "ser_regex", deserialize_with = "de_regex")]
+    pub pattern: Regex,
+    #[serde(default = "default_replacement")]
+    pub replacement: String,
+}
+
+fn default_replacement() -> String {
+    DEFAULT_REPLACEMENT.to_string()
+}
+
+fn ser_regex<S: Serializer>(re: &Regex, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(re.as_str())
+}
+
+fn de_regex<'de, D: Deserializer<'de>>(d: D) -> Result<Regex, D::Error> {
+    let raw = String::deserialize(d)?;
+    Regex::new(&raw).map_err(serde::de::Error::custom)
+}
+
+/// Apply every rule in order.
+pub fn scrub_input(rules: &[RedactionRule], input: &str) -> String {
+    let mut out = input.to_string();
+    for rule in rules {
+        if rule.pattern.is_match(&out) {
+            out = rule.pattern.replace_all(&out, rule.replacement.as_str()).into_owned();
+        }
+    }
+    out
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RedactionReport {
+    pub entries: usize,
+    pub checkpoints: usize,
+    pub entity_state: bool,
+    /// Redacted key names that collided with another key and were suffixed to keep both values.
+    pub suffixed_keys: Vec<String>,
+}
+
+/// Rewrite `pattern` out of everything a room keeps: memory, including compressed blocks,
+/// the entity state and every checkpoint. A STATE_CHANGE entry records the redaction by
+/// pattern hash, so the audit trail does not repeat the secret. Keys that redact to the same
+/// name are kept apart by suffix, and the entry names the suffixed keys.
+pub fn redact_room(room: &mut Room, pattern: &Regex, replacement: &str, now: i64) -> anyhow::Result<RedactionReport> {
+    let mut suffixed_keys = Vec::new();
+    let entries = room.memory.rewrite(|e| redact_entry(e, pattern, replacement))?;
+    let last_error = room.metadata.last_error.as_mut().is_some_and(|e| redact_str(e, pattern, replacement));
+    let entity_state = redact_state(&mut room.entity_state, pattern, replacement, &mut suffixed_keys) | last_error;
+    let checkpoints = room.checkpoints.iter_mut()
+        .filter_map(|c| redact_state(&mut c.entity_state, pattern, replacement, &mut suffixed_keys).then_some(()))
+        .count();
+    suffixed_keys.sort();
+    suffixed_keys.dedup();
+    let mut content = format!("REDACTION: {} entries, {} checkpoints rewritten", entries, checkpoints);
+    if !suffixed_keys.is_empty() {
+        content += &format!(", colliding keys kept as {}", suffixed_keys.join(", "));
+    }
+    let report = RedactionReport { entries, checkpoints, entity_state, suffixed_keys };
+
+    let mut metadata = serde_json::to_value(&report)?;
+    metadata["pattern_sha256"] = Value::String(hex::encode(Sha256::digest(pattern.as_str().as_bytes())));
+    room.memory.append(MemoryEntry{
+        seq: 0,
+        timestamp: now,
+        kind: EntryType::STATE_CHANGE,
+        content,
+        metadata,
+        pinned: false,
+    });
+    room.memory.truncate_to_fit(now);
+    Ok(report)
+}
+
+fn redact_entry(e: &mut MemoryEntry, pattern: &Regex, replacement: &str) -> bool {
+    let content = redact_str(&mut e.content, pattern, replacement);
+    let metadata = redact_value(&mut e.metadata, pattern, replacement);
+    content || metadata
+}
+
+/// Rewrite every string the state holds: kv pairs, counter names and their history, response
+/// rules, an unfinished heredoc block and the custom value. Suffixed key names are pushed to
+/// `suffixed`.
+fn redact_state(state: &mut EntityState, pattern: &Regex, replacement: &str, suffixed: &mut Vec<String>) -> bool {
+    let mut hit = false;
+    if state.kv.iter().any(|(k, v)| pattern.is_match(k) || pattern.is_match(v)) {
+        let names = redacted_names(state.kv.keys(), pattern, replacement, suffixed);
+        state.kv = std::mem::take(&mut state.kv)
+            .into_iter()
+            .map(|(k, mut v)| {
+                redact_str(&mut v, pattern, replacement);
+                (names.get(&k).cloned().unwrap_or(k), v)
+            })
+            .collect();
+        hit = true;
+    }
+    if state.counters.keys().chain(state.counter_history.keys()).any(|k| pattern.is_match(k)) {
+        // One renaming for both maps, so a counter keeps its own history.
+        let names = redacted_names(state.counters.keys().chain(state.counter_history.keys()), pattern, replacement, suffixed);
+        state.counters = std::mem::take(&mut state.counters)
+            .into_iter()
+            .map(|(k, v)| (names.get(&k).cloned().unwrap_or(k), v))
+            .collect();
+        state.counter_history = std::mem::take(&mut state.counter_history)
+            .into_iter()
+            .map(|(k, v)| (names.get(&k).cloned().unwrap_or(k), v))
+            .collect();
+        hit = true;
+    }
+    // Non-short-circuiting `|` so every string is visited.
+    for rule in &mut state.rules {
+        let mut redacted = rule.pattern.clone();
+        if redact_str(&mut redacted, pattern, replacement) {
+            rule.set_pattern(redacted);
+            hit = true;
+        }
+        hit |= rule.response.as_mut().is_some_and(|r| redact_str(r, pattern, replacement));
+    }
+    if let Some(block) = &mut state.pending {
+        hit |= redact_str(&mut block.head, pattern, replacement)
+            | block.lines.iter_mut().fold(false, |h, l| h | redact_str(l, pattern, replacement));
+    }
+    hit | redact_value(&mut state.custom, pattern, replacement)
+}
+
+/// New names for the keys that match `pattern`. A redacted name already taken, by an untouched
+/// key or an earlier redacted one, gets a `#2`, `#3`... suffix so no value is overwritten.
+/// Keys are renamed in sorted order, so the same state always gets the same names.
+fn redacted_names<'a>(keys: impl Iterator<Item = &'a String>, pattern: &Regex, replacement: &str, suffixed: &mut Vec<String>) -> HashMap<String, String> {
+    let keys: BTreeSet<&String> = keys.collect();
+    let mut taken: HashSet<String> = keys.iter().filter(|k| !pattern.is_match(k)).map(|k| k.to_string()).collect();
+    let mut names = HashMap::new();
+    for key in keys.into_iter().filter(|k| pattern.is_match(k)) {
+        let base = pattern.replace_all(key, replacement).into_owned();
+        let mut name = base.clone();
+        let mut n = 2;
+        while taken.contains(&name) {
+            name = format!("{}#{}", base, n);
+            n += 1;
+        }
+        if name != base {
+            suffixed.push(name.clone());
+        }
+        taken.insert(name.clone());
+        names.insert(key.clone(), name);
+    }
+    names
+}
+
+fn redact_value(v: &mut Value, pattern: &Regex, replacement: &str) -> bool {
+    match v {
+        Value::String(s) => redact_str(s, pattern, replacement),
+        // Non-short-circuiting `|` so every nested value is visited.
+        Value::Array(items) => items.iter_mut().fold(false, |hit, x| hit | redact_value(x, pattern, replacement)),
+        Value::Object(map) => map.values_mut().fold(false, |hit, x| hit | redact_value(x, pattern, replacement)),
+        _ => false,
+    }
+}
+
+fn redact_str(s: &mut String, pattern: &Regex, replacement: &str) -> bool {
+    if !pattern.is_match(s) {
+        return false;
+    }
+    *s = pattern.replace_all(s, replacement).into_owned();
+    true
+}
@@ -1,6 +1,12 @@
-use crate::entity::EntityState;
-use crate::memory::MemoryStore;
+use crate::config::LifecycleConfig;
+use crate::entity::{self, Entity, EntityState, DEFAULT_ENTITY};
+use crate::error::RoomError;
+use crate::history::Checkpoint;
+use crate::input::Rejection;
+use crate::memory::{self, default_pinned_capacity, EntryType, MemoryEntry, MemoryStore, RetentionPolicy};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RoomConfig {
@@ -8,6 +14,34 @@ pub struct RoomConfig {
     pub timeout_seconds: u64,
     pub compression: String,
     pub max_input_size: u64,
+    #[serde(default = "default_max_entries")]
+    pub max_entries: usize,
+    /// Inputs between entity state checkpoints; 0 disables them.
+    #[serde(default = "default_checkpoint_interval")]
+    pub checkpoint_interval: u64,
+    #[serde(default)]
+    pub retention: RetentionPolicy,
+    /// Cap on bytes held by pinned memory entries; should stay well under `memory_limit`.
+    #[serde(default = "default_pinned_capacity")]
+    pub max_pinned_bytes: u64,
+    /// Samples kept per counter for `stats --counter`; 0 keeps none.
+    #[serde(default)]
+    pub counter_history: usize,
+    /// Registry name of the entity this room runs.
+    #[serde(default = "default_entity")]
+    pub entity: String,
+}
+
+fn default_entity() -> String {
+    DEFAULT_ENTITY.to_string()
+}
+
+fn default_max_entries() -> usize {
+    1_000_000
+}
+
+fn default_checkpoint_interval() -> u64 {
+    100
 }
 
 impl Default for RoomConfig {
@@ -15,8 +49,24 @@ impl Default for RoomConfig {
         Self {
             memory_limit: 512 * 1024 * 1024,
             timeout_seconds: 30,
-            compression: "zstd".to_string(),
+            compression: memory::COMPRESSION_ALGORITHM.to_string(),
             max_input_size: 65536,
+            max_entries: default_max_entries(),
+            checkpoint_interval: default_checkpoint_interval(),
+            retention: RetentionPolicy::new(),
+            max_pinned_bytes: default_pinned_capacity(),
+            counter_history: 0,
+            entity: default_entity(),
+        }
+    }
+}
+
+impl RoomConfig {
+    /// Rooms created before the setting was checked may name any algorithm, but were always
+    /// packed with deflate; name it so.
+    pub fn normalize_compression(&mut self) {
+        if memory::check_compression(&self.compression).is_err() {
+            self.compression = memory::COMPRESSION_ALGORITHM.to_string();
         }
     }
 }
@@ -40,6 +90,12 @@ pub struct RoomMetadata {
     pub total_inputs: u64,
     pub total_outputs: u64,
     pub total_errors: u64,
+    /// Inputs abandoned because the entity exceeded `RoomConfig.timeout_seconds`.
+    #[serde(default)]
+    pub total_timeouts: u64,
+    /// Lines refused by input preprocessing, by reason. They never reach the entity.
+    #[serde(default)]
+    pub rejected_inputs: BTreeMap<Rejection, u64>,
     pub last_error: Option<String>,
     pub state_version: u64,
 }
@@ -54,6 +110,8 @@ pub struct Room {
     pub memory: MemoryStore,
     pub entity_state: EntityState,
     pub metadata: RoomMetadata,
+    #[serde(default)]
+    pub checkpoints: Vec<Checkpoint>,
 }
 
 impl Room {
@@ -61,4 +119,63 @@ impl Room {
         if self.memory.capacity == 0 { return 0; }
         ((self.memory.usage as f64 / self.memory.capacity as f64) * 100.0).round() as u64
     }
+
+    /// Change state, bumping `state_version`. Returns false if the room was already in `state`.
+    pub fn set_state(&mut self, state: RoomState) -> bool {
+        if self.state == state { return false; }
+        self.state = state;
+        self.metadata.state_version += 1;
+        true
+    }
+
+    /// Move to `state` as part of a lifecycle `event`, logging a STATE_CHANGE entry with the
+    /// before and after states. The entry is written even when the state is unchanged, so
+    /// entering an already ACTIVE room still shows up. Returns true if the state changed.
+    pub fn transition(&mut self, state: RoomState, event: &str, now: i64) -> bool {
+        let from = self.state;
+        let changed = self.set_state(state);
+        self.memory.append(MemoryEntry{
+            seq: 0,
+            timestamp: now,
+            kind: EntryType::STATE_CHANGE,
+            content: format!("STATE_CHANGE: {} {:?} -> {:?}", event.to_uppercase(), from, state),
+            metadata: serde_json::json!({"event": event, "from": from, "to": state}),
+            pinned: false,
+        });
+        if let Ok(e) = self.entity() {
+            e.on_state_change(self, from, state, now);
+        }
+        changed
+    }
+
+    /// The entity implementation this room runs.
+    pub fn entity(&self) -> Result<Arc<dyn Entity>, RoomError> {
+        entity::resolve(&self.config.entity).ok_or_else(|| RoomError::UnknownEntity { name: self.config.entity.clone() })
+    }
+
+    /// Start a session in this room.
+    pub fn enter(&mut self, now: i64) {
+        self.last_active = now;
+        self.transition(RoomState::ACTIVE, "enter", now);
+    }
+
+    /// Record activity. An IDLE room becomes ACTIVE again.
+    pub fn activate(&mut self, now: i64) {
+        self.last_active = now;
+        if self.state == RoomState::IDLE {
+            self.transition(RoomState::ACTIVE, "activity", now);
+        }
+    }
+
+    /// Apply the inactivity thresholds to this room, returning the new state if it changed.
+    pub fn apply_idle_policy(&mut self, now: i64, policy: &LifecycleConfig) -> Option<RoomState> {
+        let idle_for = now.saturating_sub(self.last_active).max(0) as u64;
+        let next = match self.state {
+            RoomState::ACTIVE | RoomState::IDLE if policy.suspend_after.is_some_and(|t| idle_for >= t) => RoomState::SUSPENDED,
+            RoomState::ACTIVE if idle_for >= policy.idle_after => RoomState::IDLE,
+            _ => return None,
+        };
+        self.transition(next, "idle_policy", now);
+        Some(next)
+    }
 }
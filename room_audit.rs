@@ -0,0 +1,45 @@
+mod common;
+
+use backrooms_terminal::config::Config;
+use backrooms_terminal::memory::EntryType;
+use backrooms_terminal::room::RoomState;
+use backrooms_terminal::session::{enter_room, process_input};
+
+#[test]
+fn entity_mutations_and_lifecycle_are_logged() {
+    let cfg = Config::load(None).unwrap();
+    let mut room = common::room_from("a", &cfg);
+
+    process_input(&mut room, "remember color: red", 101, &cfg);
+    process_input(&mut room, "remember color: blue", 102, &cfg);
+    process_input(&mut room, "increment counter visits", 103, &cfg);
+    room.transition(RoomState::IDLE, "exit", 104);
+
+    let changes: Vec<_> = room.memory.entries.iter().filter(|e| e.kind == EntryType::STATE_CHANGE).collect();
+    assert_eq!(changes.len(), 4);
+    assert_eq!(changes[0].metadata["old"], serde_json::Value::Null);
+    assert_eq!(changes[1].metadata["old"], "red");
+    assert_eq!(changes[1].metadata["new"], "blue");
+    assert_eq!(changes[2].metadata["op"], "counter_increment");
+    assert_eq!(changes[2].metadata["new"], 1);
+    assert_eq!(changes[3].metadata["from"], "ACTIVE");
+    assert_eq!(changes[3].metadata["to"], "IDLE");
+}
+
+#[test]
+fn observations_follow_enable_observations() {
+    let mut cfg = Config::load(None).unwrap();
+    let gap = cfg.lifecycle.idle_after as i64;
+    let mut room = common::room_from("o", &cfg);
+
+    enter_room(&mut room, &cfg, 100 + gap);
+    let observed = |room: &backrooms_terminal::room::Room| {
+        room.memory.entries.iter().filter(|e| e.kind == EntryType::OBSERVATION).count()
+    };
+    assert_eq!(observed(&room), 1);
+    assert!(room.memory.entries.iter().any(|e| e.content.starts_with("IDLE_GAP")));
+
+    cfg.entity.enable_observations = false;
+    process_input(&mut room, "status", 100 + 3 * gap, &cfg);
+    assert_eq!(observed(&room), 1);
+}
@@ -0,0 +1,68 @@
+use backrooms_terminal::persistence::filesystem::FilesystemPersistence;
+use backrooms_terminal::persistence::fsck::IssueKind;
+use backrooms_terminal::persistence::Persistence;
+use backrooms_terminal::room::{Room, RoomConfig, RoomMetadata, RoomState};
+use backrooms_terminal::memory::{EntryType, MemoryEntry, MemoryStore};
+use backrooms_terminal::entity::EntityState;
+use tempfile::tempdir;
+
+#[test]
+fn fsck_repairs_recomputable_fields() {
+    let dir = tempdir().unwrap();
+    let p = FilesystemPersistence::new(dir.path());
+    p.init().unwrap();
+
+    let now = 1_700_000_000i64;
+    let mut room = Room{
+        id: "test".to_string(),
+        created_at: now,
+        last_active: now,
+        state: RoomState::ACTIVE,
+        config: RoomConfig::default(),
+        memory: MemoryStore::new(1024),
+        entity_state: EntityState::default(),
+        metadata: RoomMetadata{
+            creation_timestamp: now,
+            creator_pid: 1,
+            creator_user: "u".to_string(),
+            creator_host: "h".to_string(),
+            total_inputs: 0,
+            total_outputs: 0,
+            total_errors: 0,
+            total_timeouts: 0,
+            rejected_inputs: Default::default(),
+            last_error: None,
+            state_version: 1,
+        },
+        checkpoints: vec![],
+    };
+    room.memory.append(MemoryEntry{
+        seq: 0,
+        timestamp: now,
+        kind: EntryType::INPUT,
+        content: "hello".to_string(),
+        metadata: serde_json::json!({}),
+        pinned: false,
+    });
+    room.memory.usage = 999;
+    p.save_room(&room).unwrap();
+    std::fs::create_dir(dir.path().join("orphan")).unwrap();
+
+    let report = p.fsck(false).unwrap();
+    assert_eq!(report.rooms_checked, 1);
+    let kinds: Vec<IssueKind> = report.issues.iter().map(|i| i.kind).collect();
+    assert!(kinds.contains(&IssueKind::MEMORY_USAGE_MISMATCH));
+    assert!(kinds.contains(&IssueKind::CAPACITY_MISMATCH));
+    assert!(kinds.contains(&IssueKind::COUNTER_MISMATCH));
+    assert!(kinds.contains(&IssueKind::ORPHAN_DIRECTORY));
+
+    let report = p.fsck(true).unwrap();
+    assert_eq!(report.unrepaired(), 1);
+    let loaded = p.load_room("test").unwrap();
+    assert_eq!(loaded.memory.usage, loaded.memory.computed_usage());
+    assert_eq!(loaded.memory.capacity, loaded.config.memory_limit);
+    assert_eq!(loaded.metadata.total_inputs, 1);
+
+    let report = p.fsck(true).unwrap();
+    assert_eq!(report.issues.len(), 1);
+}
@@ -0,0 +1,97 @@
+mod common;
+
+use backrooms_terminal::config::Config;
+use backrooms_terminal::entity::{self, Entity};
+use backrooms_terminal::history::reconstruct;
+use backrooms_terminal::memory::{EntryType, MemoryEntry};
+use backrooms_terminal::room::Room;
+use backrooms_terminal::session::{process_input, room_config_from};
+use std::sync::Arc;
+
+#[test]
+fn reconstructs_state_at_past_timestamps() {
+    let cfg = Config::load(None).unwrap();
+    let mut rc = room_config_from(&cfg);
+    rc.checkpoint_interval = 2;
+    let mut room = common::room("h", rc, 100);
+
+    process_input(&mut room, "remember color: red", 101, &cfg);
+    process_input(&mut room, "increment counter visits", 102, &cfg);
+    process_input(&mut room, "remember color: blue", 103, &cfg);
+    process_input(&mut room, "increment counter visits", 104, &cfg);
+    process_input(&mut room, "remember color: green", 105, &cfg);
+    assert_eq!(room.checkpoints.len(), 2);
+
+    let r = reconstruct(&room, 101).unwrap();
+    assert!(r.exact());
+    assert_eq!(r.base, None);
+    assert_eq!(r.entity_state.kv["color"], "red");
+
+    let r = reconstruct(&room, 103).unwrap();
+    assert_eq!(r.base, Some(102));
+    assert_eq!(r.replayed, 1);
+    assert_eq!(r.entity_state.kv["color"], "blue");
+    assert_eq!(r.entity_state.counters["visits"], 1);
+
+    let r = reconstruct(&room, 200).unwrap();
+    assert_eq!(r.entity_state.kv["color"], "green");
+    assert_eq!(r.entity_state.counters["visits"], 2);
+
+    // Drop everything before the second checkpoint: only replays from creation are affected.
+    room.memory.max_entries = 3;
+    room.memory.truncate_to_fit(0);
+    assert!(reconstruct(&room, 105).unwrap().exact());
+    assert!(!reconstruct(&room, 101).unwrap().exact());
+    assert!(reconstruct(&room, 99).is_err());
+}
+
+/// Seeds its state at creation and counts the inputs it is given.
+struct Seeded;
+
+impl Entity for Seeded {
+    fn name(&self) -> &str {
+        "seeded"
+    }
+
+    fn version(&self) -> &str {
+        "3.0.0"
+    }
+
+    fn init(&self, room: &mut Room, _now: i64) {
+        room.entity_state.version = self.version().to_string();
+        room.entity_state.kv.insert("origin".to_string(), "seed".to_string());
+        room.entity_state.counters.insert("seen".to_string(), 10);
+    }
+
+    fn handle_input(&self, room: &mut Room, input: &str, now: i64) -> Option<String> {
+        room.memory.append(MemoryEntry{
+            seq: 0,
+            timestamp: now,
+            kind: EntryType::INPUT,
+            content: input.to_string(),
+            metadata: serde_json::json!({}),
+            pinned: false,
+        });
+        *room.entity_state.counters.entry("seen".to_string()).or_default() += 1;
+        None
+    }
+}
+
+#[test]
+fn reconstruction_without_a_checkpoint_starts_from_init() {
+    entity::register(Arc::new(Seeded));
+    let cfg = Config::load(None).unwrap();
+    let mut rc = room_config_from(&cfg);
+    rc.entity = "seeded".to_string();
+    rc.checkpoint_interval = 0;
+    let mut room = common::room("s", rc, 100);
+    process_input(&mut room, "one", 101, &cfg);
+    process_input(&mut room, "two", 102, &cfg);
+
+    let r = reconstruct(&room, 101).unwrap();
+    assert_eq!(r.base, None);
+    assert_eq!(r.entity_state.version, "3.0.0");
+    assert_eq!(r.entity_state.kv["origin"], "seed");
+    assert_eq!(r.entity_state.counters["seen"], 11);
+    assert_eq!(reconstruct(&room, 200).unwrap().entity_state.counters["seen"], room.entity_state.counters["seen"]);
+}
@@ -28,12 +28,52 @@ fn create_save_load_room() {
             total_inputs: 0,
             total_outputs: 0,
             total_errors: 0,
+            total_timeouts: 0,
+            rejected_inputs: Default::default(),
             last_error: None,
             state_version: 1,
         },
+        checkpoints: vec![],
     };
 
     p.save_room(&room).unwrap();
     let loaded = p.load_room("test").unwrap();
     assert_eq!(loaded.id, "test");
 }
+
+#[test]
+fn baseline_room_loads_with_recomputed_usage() {
+    let dir = tempdir().unwrap();
+    let p = FilesystemPersistence::new(dir.path());
+    p.init().unwrap();
+
+    // As saved before entries carried a seq: usage is the sum of content lengths.
+    let raw = serde_json::json!({
+        "id": "old",
+        "created_at": 100,
+        "last_active": 200,
+        "state": "IDLE",
+        "config": {"memory_limit": 1024, "timeout_seconds": 30, "compression": "zstd", "max_input_size": 65536},
+        "memory": {
+            "entries": [
+                {"timestamp": 100, "kind": "INPUT", "content": "remember a: b", "metadata": {}},
+                {"timestamp": 101, "kind": "OUTPUT", "content": "ENTITY: Stored.", "metadata": {}}
+            ],
+            "capacity": 1024,
+            "usage": 28
+        },
+        "entity_state": {"kv": {"a": "b"}, "counters": {}, "version": "2.1.0"},
+        "metadata": {
+            "creation_timestamp": 100, "creator_pid": 1, "creator_user": "u", "creator_host": "h",
+            "total_inputs": 1, "total_outputs": 1, "total_errors": 0, "last_error": null, "state_version": 1
+        }
+    });
+    std::fs::create_dir_all(dir.path().join("old")).unwrap();
+    std::fs::write(dir.path().join("old").join("room.json"), raw.to_string()).unwrap();
+
+    let room = p.load_room("old").unwrap();
+    let seqs: Vec<u64> = room.memory.entries_from(0).unwrap().iter().map(|e| e.seq).collect();
+    assert_eq!(seqs, vec![0, 1]);
+    assert_eq!(room.memory.usage, room.memory.computed_usage());
+    assert!(room.memory.usage > 28);
+}
@@ -0,0 +1,120 @@
+mod common;
+
+use backrooms_terminal::config::Config;
+use backrooms_terminal::persistence::filesystem::FilesystemPersistence;
+use backrooms_terminal::persistence::Persistence;
+use backrooms_terminal::session;
+use tempfile::tempdir;
+
+#[test]
+fn create_respects_room_and_memory_limits() {
+    let dir = tempdir().unwrap();
+    let p = FilesystemPersistence::new(dir.path());
+    p.init().unwrap();
+
+    let mut cfg = Config::load(None).unwrap();
+    cfg.limits.max_rooms = 2;
+    cfg.limits.max_room_memory = 1024;
+
+    let rc = session::room_config_from(&cfg);
+    let mut a = common::room("a", rc.clone(), 0);
+    session::check_create_limits(&p, &cfg.limits, &a).unwrap();
+    session::process_input(&mut a, "remember k: v", 1, &cfg);
+    p.save_room(&a).unwrap();
+
+    // Only what rooms hold counts against the budget, not their configured limits.
+    let b = common::room("b", rc.clone(), 0);
+    cfg.limits.max_total_memory = a.memory.usage + b.memory.usage + 1;
+    session::check_create_limits(&p, &cfg.limits, &b).unwrap();
+    cfg.limits.max_total_memory = a.memory.usage;
+    let err = session::check_create_limits(&p, &cfg.limits, &b).unwrap_err();
+    assert!(err.to_string().starts_with("ERROR: GLOBAL_MEMORY_EXCEEDED"));
+
+    let mut big = rc.clone();
+    big.memory_limit = 2048;
+    let err = session::check_create_limits(&p, &cfg.limits, &common::room("c", big, 0)).unwrap_err();
+    assert!(err.to_string().starts_with("ERROR: ROOM_MEMORY_LIMIT_EXCEEDED"));
+
+    cfg.limits.max_total_memory = u64::MAX;
+    p.save_room(&b).unwrap();
+    let err = session::check_create_limits(&p, &cfg.limits, &b).unwrap_err();
+    assert!(err.to_string().starts_with("ERROR: ROOM_LIMIT_REACHED"));
+}
+
+#[test]
+fn input_growth_is_held_to_the_global_budget() {
+    let dir = tempdir().unwrap();
+    let p = FilesystemPersistence::new(dir.path());
+    p.init().unwrap();
+
+    let mut cfg = Config::load(None).unwrap();
+    let rc = session::room_config_from(&cfg);
+    let mut other = common::room("other", rc.clone(), 0);
+    for i in 0..4 {
+        session::process_input(&mut other, &format!("remember k{}: v", i), 1, &cfg);
+    }
+    p.save_room(&other).unwrap();
+
+    let mut room = common::room("r", rc, 0);
+    for i in 0..4 {
+        session::process_input(&mut room, &format!("remember k{}: v", i), 2, &cfg);
+    }
+    let usage = room.memory.usage;
+    cfg.limits.max_total_memory = other.memory.usage + usage;
+    session::enforce_global_memory(&p, &mut room, &cfg, 3).unwrap();
+    assert_eq!(room.memory.usage, usage);
+
+    session::process_input(&mut room, "remember k4: v", 4, &cfg);
+    session::enforce_global_memory(&p, &mut room, &cfg, 5).unwrap();
+    assert!(room.memory.usage <= usage);
+    assert_eq!(room.memory.capacity, room.config.memory_limit);
+    assert!(room.memory.entries_from(0).unwrap().iter().any(|e| e.content.starts_with("MEMORY_PRESSURE: ")));
+}
+
+#[test]
+fn preprocessing_rejects_before_the_entity() {
+    use backrooms_terminal::input::{preprocess, Rejection};
+    use backrooms_terminal::memory::EntryType;
+
+    assert_eq!(preprocess(b"rem\0ember a: b \t\r\n", 64).unwrap(), "remember a: b");
+    assert_eq!(preprocess(b"\xff\xfe", 64).unwrap_err(), Rejection::INVALID_INPUT_ENCODING);
+    assert_eq!(preprocess(&[b'x'; 65], 64).unwrap_err(), Rejection::INPUT_SIZE_EXCEEDED);
+    assert_eq!(preprocess(&[b'x'; 64], 64).unwrap().len(), 64);
+
+    let cfg = Config::load(None).unwrap();
+    let mut rc = session::room_config_from(&cfg);
+    rc.max_input_size = 16;
+    let mut room = common::room("p", rc, 0);
+    let before = room.memory.len();
+
+    let out = session::process_raw_input(&mut room, b"bad \xc3\x28 bytes\n", 1, &cfg);
+    assert_eq!(out.as_deref(), Some("ERROR: INVALID_INPUT_ENCODING"));
+    let out = session::process_raw_input(&mut room, &[b'y'; 17], 2, &cfg);
+    assert_eq!(out.as_deref(), Some("ERROR: INPUT_SIZE_EXCEEDED (max 16 bytes)"));
+    session::process_raw_input(&mut room, &[b'z'; 17], 3, &cfg);
+    assert_eq!(room.memory.len(), before);
+    assert_eq!(room.metadata.total_inputs, 0);
+    assert_eq!(room.metadata.rejected_inputs[&Rejection::INVALID_INPUT_ENCODING], 1);
+    assert_eq!(room.metadata.rejected_inputs[&Rejection::INPUT_SIZE_EXCEEDED], 2);
+
+    let out = session::process_raw_input(&mut room, b"remember k: v\0\r\n", 4, &cfg);
+    assert_eq!(out.as_deref(), Some("ENTITY: Stored."));
+    assert_eq!(room.entity_state.kv["k"], "v");
+    assert_eq!(room.memory.count_of(EntryType::INPUT), 1);
+}
+
+#[test]
+fn default_limits_fit_max_rooms_default_rooms() {
+    let dir = tempdir().unwrap();
+    let p = FilesystemPersistence::new(dir.path());
+    p.init().unwrap();
+
+    let cfg = Config::load(None).unwrap();
+    let rc = session::room_config_from(&cfg);
+    assert_eq!(cfg.limits.max_total_memory, 16 * 1024 * 1024 * 1024);
+    for i in 0..40 {
+        let room = common::room(&format!("r{}", i), rc.clone(), 0);
+        session::check_create_limits(&p, &cfg.limits, &room).unwrap();
+        p.save_room(&room).unwrap();
+    }
+}
@@ -1,6 +1,24 @@
+mod common;
+
 use backrooms_terminal::room::{RoomState};
 
 #[test]
 fn room_state_enum_works() {
     assert_eq!(format!("{:?}", RoomState::ACTIVE), "ACTIVE");
 }
+
+#[test]
+fn idle_policy_transitions() {
+    use backrooms_terminal::config::LifecycleConfig;
+    use backrooms_terminal::room::RoomConfig;
+
+    let mut room = common::room("r", RoomConfig::default(), 0);
+    let policy = LifecycleConfig { idle_after: 10, suspend_after: Some(100), sweep_interval: 1 };
+
+    assert_eq!(room.apply_idle_policy(5, &policy), None);
+    assert_eq!(room.apply_idle_policy(10, &policy), Some(RoomState::IDLE));
+    room.activate(50);
+    assert_eq!(room.state, RoomState::ACTIVE);
+    assert_eq!(room.apply_idle_policy(150, &policy), Some(RoomState::SUSPENDED));
+    assert_eq!(room.metadata.state_version, 4);
+}
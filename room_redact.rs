@@ -0,0 +1,110 @@
+mod common;
+
+use backrooms_terminal::config::Config;
+use backrooms_terminal::memory::EntryType;
+use backrooms_terminal::redact::{redact_room, RedactionRule};
+use backrooms_terminal::session::{process_input, room_config_from};
+use regex::Regex;
+
+#[test]
+fn ingest_rules_scrub_inputs_before_recording() {
+    let mut cfg = Config::load(None).unwrap();
+    cfg.redaction = vec![RedactionRule{
+        name: "token".to_string(),
+        pattern: Regex::new(r"tok_[a-z0-9]+").unwrap(),
+        replacement: "[REDACTED]".to_string(),
+    }];
+    let mut room = common::room_from("r", &cfg);
+
+    process_input(&mut room, "remember api: tok_abc123", 101, &cfg);
+    let input = room.memory.entries.iter().find(|e| e.kind == EntryType::INPUT).unwrap();
+    assert_eq!(input.content, "remember api: [REDACTED]");
+    assert_eq!(room.entity_state.kv["api"], "[REDACTED]");
+}
+
+#[test]
+fn redact_rewrites_memory_blocks_and_checkpoints() {
+    let cfg = Config::load(None).unwrap();
+    let mut rc = room_config_from(&cfg);
+    rc.checkpoint_interval = 1;
+    rc.memory_limit = 8192;
+    let mut room = common::room("r", rc, 100);
+
+    process_input(&mut room, "remember password: hunter2", 101, &cfg);
+    for i in 0..40 {
+        process_input(&mut room, &format!("remember filler{}: repetitive value repetitive value", i), 102 + i, &cfg);
+    }
+    assert!(!room.memory.blocks.is_empty());
+    assert!(room.checkpoints.iter().any(|c| c.entity_state.kv.get("password").is_some_and(|v| v == "hunter2")));
+
+    let revision = room.memory.revision;
+    let report = redact_room(&mut room, &Regex::new("hunter2").unwrap(), "[REDACTED]", 200).unwrap();
+    assert!(report.entries >= 1);
+    assert!(report.checkpoints >= 1);
+    assert!(report.entity_state);
+    assert!(room.memory.revision > revision);
+
+    let all = room.memory.all_entries().unwrap();
+    assert!(all.iter().all(|e| !e.content.contains("hunter2")));
+    assert!(serde_json::to_string(&room).unwrap().find("hunter2").is_none());
+    let audit = all.last().unwrap();
+    assert_eq!(audit.kind, EntryType::STATE_CHANGE);
+    assert!(audit.metadata["pattern_sha256"].is_string());
+    assert_eq!(room.memory.usage, room.memory.computed_usage());
+}
+
+#[test]
+fn redact_reaches_counters_rules_pending_blocks_and_last_error() {
+    let cfg = Config::load(None).unwrap();
+    let mut rc = room_config_from(&cfg);
+    rc.counter_history = 4;
+    let mut room = common::room("r", rc, 100);
+
+    process_input(&mut room, "initialize counter hunter2_hits", 101, &cfg);
+    process_input(&mut room, "increment counter hunter2_hits", 102, &cfg);
+    process_input(&mut room, "rule add exact say hunter2 => the word is hunter2", 103, &cfg);
+    process_input(&mut room, "remember note: <<END", 104, &cfg);
+    process_input(&mut room, "hunter2 on its own line", 105, &cfg);
+    room.metadata.last_error = Some("PARSE_ERROR (hunter2)".to_string());
+    assert!(room.entity_state.counter_history.contains_key("hunter2_hits"));
+    assert!(room.entity_state.pending.is_some());
+
+    let report = redact_room(&mut room, &Regex::new("hunter2").unwrap(), "[REDACTED]", 200).unwrap();
+    assert!(report.entity_state);
+    assert_eq!(room.entity_state.counters["[REDACTED]_hits"], 1);
+    assert!(room.entity_state.counter_history.contains_key("[REDACTED]_hits"));
+    assert!(room.entity_state.rules.iter().any(|r| r.pattern == "say [REDACTED]"));
+    assert_eq!(room.metadata.last_error.as_deref(), Some("PARSE_ERROR ([REDACTED])"));
+    assert!(serde_json::to_string(&room).unwrap().find("hunter2").is_none());
+}
+
+#[test]
+fn redacted_keys_that_collide_keep_both_values() {
+    let cfg = Config::load(None).unwrap();
+    let mut rc = room_config_from(&cfg);
+    rc.counter_history = 4;
+    let mut room = common::room("c", rc, 100);
+
+    process_input(&mut room, "remember token_abc1: first", 101, &cfg);
+    process_input(&mut room, "remember token_abc2: second", 102, &cfg);
+    process_input(&mut room, "initialize counter hits_abc1", 103, &cfg);
+    process_input(&mut room, "increment counter hits_abc2", 104, &cfg);
+    process_input(&mut room, "increment counter hits_abc2", 105, &cfg);
+
+    let report = redact_room(&mut room, &Regex::new("abc[0-9]").unwrap(), "[REDACTED]", 200).unwrap();
+    assert_eq!(report.suffixed_keys, vec!["hits_[REDACTED]#2", "token_[REDACTED]#2"]);
+    let kv = &room.entity_state.kv;
+    assert_eq!(kv.len(), 2);
+    assert_eq!(kv["token_[REDACTED]"], "first");
+    assert_eq!(kv["token_[REDACTED]#2"], "second");
+    let counters = &room.entity_state.counters;
+    assert_eq!(counters["hits_[REDACTED]"], 0);
+    assert_eq!(counters["hits_[REDACTED]#2"], 2);
+    assert!(room.entity_state.counter_history.contains_key("hits_[REDACTED]#2"));
+
+    let audit = room.memory.entries_from(0).unwrap().into_iter().rev()
+        .find(|e| e.kind == EntryType::STATE_CHANGE)
+        .unwrap();
+    assert!(audit.content.ends_with(", colliding keys kept as hits_[REDACTED]#2, token_[REDACTED]#2"));
+    assert_eq!(audit.metadata["suffixed_keys"][1], "token_[REDACTED]#2");
+}
@@ -0,0 +1,176 @@
+use crate::entity::EntityState;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::OnceLock;
+
+/// How a response rule's pattern is compared with the input.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MatchKind {
+    /// The whole trimmed input equals the pattern.
+    EXACT,
+    /// `*` matches any run of characters and `?` any one character, over the whole input.
+    GLOB,
+    /// The pattern matches anywhere in the input unless anchored.
+    REGEX,
+}
+
+impl fmt::Display for MatchKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MatchKind::EXACT => "exact",
+            MatchKind::GLOB => "glob",
+            MatchKind::REGEX => "regex",
+        })
+    }
+}
+
+pub fn parse_match_kind(s: &str) -> Option<MatchKind> {
+    match s.to_ascii_lowercase().as_str() {
+        "exact" => Some(MatchKind::EXACT),
+        "glob" => Some(MatchKind::GLOB),
+        "regex" => Some(MatchKind::REGEX),
+        _ => None,
+    }
+}
+
+/// What the built-in entity does with free text that matches `pattern`: respond with the
+/// template, or stay silent when there is none.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResponseRule {
+    pub id: u64,
+    pub priority: i64,
+    pub kind: MatchKind,
+    pub pattern: String,
+    pub response: Option<String>,
+    #[serde(skip)]
+    compiled: Compiled,
+}
+
+/// A regex rule's pattern, compiled on first use. Not persisted and ignored by comparisons.
+#[derive(Debug, Clone, Default)]
+struct Compiled(OnceLock<Option<Regex>>);
+
+impl PartialEq for Compiled {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Compiled {}
+
+impl fmt::Display for ResponseRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{} priority {} {} {} => {}", self.id, self.priority, self.kind, self.pattern, self.response.as_deref().unwrap_or(SILENCE))
+    }
+}
+
+/// Response keyword that makes a rule swallow the input.
+pub const SILENCE: &str = "silence";
+
+/// Cap on compiled regex size, so a rule cannot make every input expensive.
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+pub fn compile(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern).size_limit(REGEX_SIZE_LIMIT).build()
+}
+
+/// The rules every new room starts with: inputs of only punctuation and digits are ignored
+/// and any other free text is acknowledged. They can be listed and removed like any other.
+pub fn default_rules() -> Vec<ResponseRule> {
+    vec![
+        ResponseRule::new(1, -100, MatchKind::REGEX, "^[[:punct:][:digit:]]{1,5}$".to_string(), None),
+        ResponseRule::new(2, -200, MatchKind::GLOB, "*".to_string(), Some("Acknowledged.".to_string())),
+    ]
+}
+
+impl ResponseRule {
+    pub fn new(id: u64, priority: i64, kind: MatchKind, pattern: String, response: Option<String>) -> Self {
+        Self { id, priority, kind, pattern, response, compiled: Compiled::default() }
+    }
+
+    /// Replace the pattern, dropping the compiled one.
+    pub fn set_pattern(&mut self, pattern: String) {
+        self.pattern = pattern;
+        self.compiled = Compiled::default();
+    }
+
+    pub fn matches(&self, input: &str) -> bool {
+        match self.kind {
+            MatchKind::EXACT => input == self.pattern,
+            MatchKind::GLOB => glob_match(&self.pattern, input),
+            MatchKind::REGEX => match self.compiled.0.get_or_init(|| compile(&self.pattern).ok()) {
+                // `pattern` is public, so a rule edited in place is compiled afresh.
+                Some(re) if re.as_str() == self.pattern => re.is_match(input),
+                _ => compile(&self.pattern).is_ok_and(|re| re.is_match(input)),
+            },
+        }
+    }
+}
+
+/// Rules in evaluation order: highest priority first, then oldest first.
+pub fn ordered(rules: &[ResponseRule]) -> Vec<&ResponseRule> {
+    let mut sorted: Vec<&ResponseRule> = rules.iter().collect();
+    sorted.sort_by_key(|r| (std::cmp::Reverse(r.priority), r.id));
+    sorted
+}
+
+/// The first rule, in evaluation order, that matches the input.
+pub fn find<'a>(rules: &'a [ResponseRule], input: &str) -> Option<&'a ResponseRule> {
+    ordered(rules).into_iter().find(|r| r.matches(input))
+}
+
+/// Fill in a response template. `{kv:<key>}` is the stored value, `{counter:<name>}` the
+/// counter value and `{input}` the input itself; missing keys and counters render empty.
+/// Anything else in braces is kept as written.
+pub fn render(template: &str, state: &EntityState, input: &str) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let tail = &rest[start..];
+        let Some(end) = tail.find('}') else {
+            out.push_str(tail);
+            return out;
+        };
+        let name = &tail[1..end];
+        match name.split_once(':') {
+            _ if name == "input" => out.push_str(input),
+            Some(("kv", key)) => out.push_str(state.kv.get(key).map_or("", String::as_str)),
+            Some(("counter", key)) => {
+                if let Some(v) = state.counters.get(key) {
+                    out.push_str(&v.to_string());
+                }
+            }
+            _ => out.push_str(&tail[..=end]),
+        }
+        rest = &tail[end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn glob_match(pattern: &str, input: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let s: Vec<char> = input.chars().collect();
+    let (mut pi, mut si) = (0, 0);
+    // Position of the last `*` and the input position it is currently matched up to.
+    let mut star: Option<(usize, usize)> = None;
+    while si < s.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == s[si]) {
+            pi += 1;
+            si += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, si));
+            pi += 1;
+        } else if let Some((sp, ss)) = star {
+            pi = sp + 1;
+            si = ss + 1;
+            star = Some((sp, ss + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
@@ -0,0 +1,460 @@
+use crate::config::{Config, LifecycleConfig, LimitsConfig};
+use crate::entity::{Entity, EntityState, Output, OutputLevel, OutputSink};
+use crate::error::RoomError;
+use crate::history;
+use crate::input;
+use crate::redact;
+use crate::memory::{EntryType, MemoryEntry, MemoryStore};
+use crate::persistence::Persistence;
+use crate::room::{Room, RoomConfig, RoomMetadata, RoomState};
+use sha2::{Digest, Sha256};
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+
+pub fn now_ts() -> i64 {
+    OffsetDateTime::now_utc().unix_timestamp()
+}
+
+pub fn make_room_id(origin: &str) -> String {
+    let ts = now_ts().to_string();
+    let seed = uuid::Uuid::new_v4().to_string();
+    let mut h = Sha256::new();
+    h.update(ts.as_bytes());
+    h.update(seed.as_bytes());
+    h.update(origin.as_bytes());
+    hex::encode(h.finalize())
+}
+
+/// Per-room defaults pulled from system configuration.
+pub fn room_config_from(cfg: &Config) -> RoomConfig {
+    RoomConfig{
+        memory_limit: cfg.limits.max_room_memory,
+        timeout_seconds: cfg.limits.entity_timeout,
+        compression: cfg.persistence.compression.clone(),
+        max_input_size: cfg.limits.max_input_size as u64,
+        max_entries: cfg.limits.memory_entries_max,
+        retention: cfg.limits.retention.clone(),
+        max_pinned_bytes: cfg.limits.max_pinned_memory,
+        counter_history: cfg.entity.counter_history,
+        ..RoomConfig::default()
+    }
+}
+
+/// Build a room and run its entity's `init`. Callers validate `config.entity` first; an
+/// unregistered kind leaves the room uninitialized and every input reports UNKNOWN_ENTITY.
+pub fn new_room(id: String, config: RoomConfig, now: i64, creator_user: String, creator_host: String) -> Room {
+    let mut room = Room{
+        id,
+        created_at: now,
+        last_active: now,
+        state: RoomState::ACTIVE,
+        memory: MemoryStore::new(config.memory_limit)
+            .with_max_entries(config.max_entries)
+            .with_retention(config.retention.clone())
+            .with_pinned_capacity(config.max_pinned_bytes),
+        config,
+        entity_state: EntityState::default(),
+        metadata: RoomMetadata{
+            creation_timestamp: now,
+            creator_pid: std::process::id(),
+            creator_user,
+            creator_host,
+            total_inputs: 0,
+            total_outputs: 0,
+            total_errors: 0,
+            total_timeouts: 0,
+            rejected_inputs: Default::default(),
+            last_error: None,
+            state_version: 1,
+        },
+        checkpoints: vec![],
+    };
+    if let Ok(e) = room.entity() {
+        e.init(&mut room, now);
+    }
+    room
+}
+
+/// Refuse a new room that would exceed the room count, per-room memory or global memory limits.
+/// The global budget is checked against what stored rooms actually hold, plus the new room's usage.
+pub fn check_create_limits(persistence: &dyn Persistence, limits: &LimitsConfig, room: &Room) -> anyhow::Result<()> {
+    if room.config.memory_limit > limits.max_room_memory {
+        return Err(RoomError::RoomMemoryLimitExceeded { requested: room.config.memory_limit, max: limits.max_room_memory }.into());
+    }
+    let rooms = persistence.list_rooms()?;
+    if rooms.len() >= limits.max_rooms {
+        return Err(RoomError::RoomLimitReached { max: limits.max_rooms }.into());
+    }
+    let used: u64 = rooms.iter().map(|r| r.memory_usage).sum();
+    let available = limits.max_total_memory.saturating_sub(used);
+    // A spent budget refuses even a room that starts out empty.
+    if available == 0 || room.memory.usage > available {
+        return Err(RoomError::GlobalMemoryExceeded { requested: room.memory.usage, available, limit: limits.max_total_memory }.into());
+    }
+    Ok(())
+}
+
+/// Hold `room` to its share of `max_total_memory` after an input grew it: whatever the other
+/// stored rooms do not use. Past that share the oldest entries are evicted as under memory
+/// pressure, and the eviction is observed.
+pub fn enforce_global_memory(persistence: &dyn Persistence, room: &mut Room, cfg: &Config, now: i64) -> anyhow::Result<()> {
+    let elsewhere: u64 = persistence.list_rooms()?.iter()
+        .filter(|r| r.id != room.id)
+        .map(|r| r.memory_usage)
+        .sum();
+    let share = cfg.limits.max_total_memory.saturating_sub(elsewhere);
+    if room.memory.usage <= share {
+        return Ok(());
+    }
+    let capacity = room.memory.capacity;
+    room.memory.capacity = capacity.min(share);
+    let evicted = room.memory.truncate_to_fit(now);
+    if evicted > 0 && cfg.entity.enable_observations {
+        let usage = room.memory.usage;
+        observe(room, cfg, now,
+            format!("MEMORY_PRESSURE: {} entries evicted for the global budget, {} / {} bytes", evicted, usage, share),
+            serde_json::json!({"evicted": evicted, "usage": usage, "share": share, "limit": cfg.limits.max_total_memory}));
+        room.memory.truncate_to_fit(now);
+    }
+    room.memory.capacity = capacity;
+    Ok(())
+}
+
+pub fn check_enterable(room: &Room) -> Result<(), RoomError> {
+    match room.state {
+        RoomState::SUSPENDED => Err(RoomError::Suspended),
+        RoomState::CORRUPTED => Err(RoomError::Corrupted),
+        _ => Ok(()),
+    }
+}
+
+/// Apply the idle policy to every stored room, saving those that changed state.
+/// Returns `(room_id, from, to)` for each transition.
+pub fn sweep(persistence: &dyn Persistence, policy: &LifecycleConfig, now: i64) -> anyhow::Result<Vec<(String, RoomState, RoomState)>> {
+    let mut changes = vec![];
+    for summary in persistence.list_rooms()? {
+        if !matches!(summary.state, RoomState::ACTIVE | RoomState::IDLE) { continue; }
+        let mut room = persistence.load_room(&summary.id)?;
+        let from = room.state;
+        if let Some(to) = room.apply_idle_policy(now, policy) {
+            persistence.save_room(&room)?;
+            changes.push((room.id, from, to));
+        }
+    }
+    Ok(changes)
+}
+
+/// Record an OBSERVATION entry when `entity.enable_observations` is set.
+pub fn observe(room: &mut Room, cfg: &Config, now: i64, content: String, metadata: serde_json::Value) {
+    if !cfg.entity.enable_observations {
+        return;
+    }
+    room.memory.append(MemoryEntry{
+        seq: 0,
+        timestamp: now,
+        kind: EntryType::OBSERVATION,
+        content,
+        metadata,
+        pinned: false,
+    });
+}
+
+/// Observe a gap since the room's last activity of at least `lifecycle.idle_after`.
+fn observe_idle_gap(room: &mut Room, cfg: &Config, now: i64) {
+    let gap = now.saturating_sub(room.last_active);
+    if gap >= cfg.lifecycle.idle_after as i64 {
+        observe(room, cfg, now, format!("IDLE_GAP: {}s since last activity", gap),
+            serde_json::json!({"gap": gap, "last_active": room.last_active}));
+    }
+}
+
+/// Start a session in `room`, observing how long it sat unused.
+pub fn enter_room(room: &mut Room, cfg: &Config, now: i64) {
+    observe_idle_gap(room, cfg, now);
+    room.enter(now);
+}
+
+/// Run the compression pass once usage crosses the configured threshold, then apply retention
+/// rules and truncate whatever compression could not make room for. Both are observed; the
+/// COMPRESSION_* report goes to `out`, so the transport decides where it is shown.
+pub fn enforce_memory_bounds(room: &mut Room, cfg: &Config, now: i64, out: &mut dyn OutputSink) {
+    if room.config.compression != "none" {
+        match room.memory.compress(cfg.entity.memory_compression_threshold, now) {
+            Ok(Some(report)) => {
+                for line in report.lines() {
+                    out.send(Output::new(OutputLevel::INFO, line));
+                }
+                observe(room, cfg, now,
+                    format!("COMPRESSION: {} entries packed, {} -> {} bytes",
+                        report.entries_packed, report.size_before, report.size_after),
+                    serde_json::to_value(&report).unwrap_or_default());
+            }
+            Ok(None) => {}
+            Err(e) => out.send(Output::new(OutputLevel::WARN, format!("COMPRESSION_FAILED: {}", e))),
+        }
+    }
+    let evicted = room.memory.truncate_to_fit(now);
+    if evicted > 0 && cfg.entity.enable_observations {
+        let (usage, capacity) = (room.memory.usage, room.memory.capacity);
+        observe(room, cfg, now,
+            format!("MEMORY_PRESSURE: {} entries evicted, {} / {} bytes", evicted, usage, capacity),
+            serde_json::json!({"evicted": evicted, "usage": usage, "capacity": capacity}));
+        // Make room for the observation itself; what that evicts is not logged again.
+        room.memory.truncate_to_fit(now);
+    }
+}
+
+/// Entity output as the session sees it: lines are forwarded to the transport as they are
+/// emitted and kept for recording once the input completes. Past `max_output_size` bytes per
+/// input, the line that crosses the limit is cut short and the rest is dropped.
+struct Emitted<'a> {
+    out: &'a mut dyn OutputSink,
+    lines: Vec<Output>,
+    bytes: usize,
+    max: usize,
+    omitted: usize,
+}
+
+impl OutputSink for Emitted<'_> {
+    fn send(&mut self, mut output: Output) {
+        let room_left = self.max - self.bytes;
+        if output.text.len() > room_left {
+            let mut cut = room_left;
+            while !output.text.is_char_boundary(cut) {
+                cut -= 1;
+            }
+            self.omitted += output.text.len() - cut;
+            if cut == 0 {
+                return;
+            }
+            output.text.truncate(cut);
+        }
+        self.bytes += output.text.len();
+        self.lines.push(output.clone());
+        self.out.send(output);
+    }
+}
+
+impl Emitted<'_> {
+    /// The marker closing a truncated response, if any output was dropped.
+    fn truncation_marker(&self) -> Option<Output> {
+        (self.omitted > 0).then(|| {
+            Output::new(OutputLevel::WARN, format!("[OUTPUT TRUNCATED: {} bytes over the {} byte limit]", self.omitted, self.max))
+                .with_payload(serde_json::json!({"truncated": self.omitted, "max": self.max}))
+        })
+    }
+}
+
+/// Sends lines from the entity thread back to the session thread.
+struct ChannelSink(mpsc::Sender<Output>);
+
+impl OutputSink for ChannelSink {
+    fn send(&mut self, output: Output) {
+        let _ = self.0.send(output);
+    }
+}
+
+/// What an entity may change while handling an input, taken before it runs so a timed-out or
+/// panicked input can be undone without copying the room's memory.
+struct Snapshot {
+    state: RoomState,
+    entity_state: EntityState,
+    metadata: RoomMetadata,
+    next_seq: u64,
+}
+
+impl Snapshot {
+    fn take(room: &Room) -> Self {
+        Self {
+            state: room.state,
+            entity_state: room.entity_state.clone(),
+            metadata: room.metadata.clone(),
+            next_seq: room.memory.next_seq(),
+        }
+    }
+
+    fn restore(self, room: &mut Room) {
+        room.state = self.state;
+        room.entity_state = self.entity_state;
+        room.metadata = self.metadata;
+        room.memory.rollback(self.next_seq);
+    }
+}
+
+/// Run the entity for at most `timeout_seconds` (0 waits indefinitely), on a thread of its own
+/// when there is a limit. Output reaches `out` as it is emitted. If the entity panics or
+/// overruns, the room is put back as it was before the input. An overrunning entity is asked
+/// to `abort` and then waited for, so it never keeps running on the room unseen and the room
+/// takes no further input until it has stopped; what it emits after the deadline is dropped.
+fn invoke_entity(entity: &dyn Entity, room: &mut Room, input: &str, now: i64, out: &mut Emitted) -> Result<(), RoomError> {
+    let seconds = room.config.timeout_seconds;
+    let snapshot = Snapshot::take(room);
+    let result = if seconds == 0 {
+        panic::catch_unwind(AssertUnwindSafe(|| entity.stream_input(room, input, now, out))).map_err(crashed).and_then(|r| r)
+    } else {
+        watch(entity, room, input, now, seconds, out)
+    };
+    if result.is_err() {
+        snapshot.restore(room);
+    }
+    result
+}
+
+fn watch(entity: &dyn Entity, room: &mut Room, input: &str, now: i64, seconds: u64, out: &mut Emitted) -> Result<(), RoomError> {
+    let room_id = room.id.clone();
+    thread::scope(|scope| {
+        let (tx, rx) = mpsc::channel();
+        let worker = scope.spawn(move || entity.stream_input(room, input, now, &mut ChannelSink(tx)));
+        let deadline = Instant::now() + Duration::from_secs(seconds);
+        loop {
+            match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(output) => out.send(output),
+                Err(RecvTimeoutError::Timeout) => {
+                    entity.abort(&room_id);
+                    drop(rx);
+                    let _ = worker.join();
+                    return Err(RoomError::EntityTimeout { seconds });
+                }
+                Err(RecvTimeoutError::Disconnected) => return worker.join().map_err(crashed).and_then(|r| r),
+            }
+        }
+    })
+}
+
+fn crashed(payload: Box<dyn Any + Send>) -> RoomError {
+    let message = payload.downcast_ref::<&str>().map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string());
+    RoomError::EntityCrashed { message }
+}
+
+/// Account for ERROR entries the entity recorded itself, so `total_errors` and `last_error`
+/// match memory.
+fn count_entity_errors(room: &mut Room, from_seq: u64) {
+    let recorded: Vec<&MemoryEntry> = room.memory.entries.iter().rev()
+        .take_while(|e| e.seq >= from_seq)
+        .filter(|e| e.kind == EntryType::ERROR)
+        .collect();
+    if let Some(latest) = recorded.first() {
+        room.metadata.last_error = Some(latest.content.clone());
+    }
+    room.metadata.total_errors += recorded.len() as u64;
+}
+
+/// Preprocess one line of raw transport bytes and, if it is accepted, run it through the
+/// entity. Rejected lines are counted by reason and never reach the entity or memory.
+pub fn stream_raw_input(room: &mut Room, raw: &[u8], now: i64, cfg: &Config, out: &mut dyn OutputSink) {
+    match input::preprocess(raw, room.config.max_input_size) {
+        Ok(line) => stream_input(room, &line, now, cfg, out),
+        Err(reason) => {
+            *room.metadata.rejected_inputs.entry(reason).or_default() += 1;
+            out.emit(&reason.error(room.config.max_input_size).to_string());
+        }
+    }
+}
+
+/// `stream_raw_input`, collecting the output.
+pub fn process_raw_input(room: &mut Room, raw: &[u8], now: i64, cfg: &Config) -> Option<String> {
+    let mut lines = Vec::new();
+    stream_raw_input(room, raw, now, cfg, &mut lines);
+    joined(lines)
+}
+
+/// `stream_input`, collecting the output. Returns the lines emitted, if any, joined by newlines.
+pub fn process_input(room: &mut Room, input: &str, now: i64, cfg: &Config) -> Option<String> {
+    let mut lines = Vec::new();
+    stream_input(room, input, now, cfg, &mut lines);
+    joined(lines)
+}
+
+fn joined(lines: Vec<String>) -> Option<String> {
+    (!lines.is_empty()).then(|| lines.join("\n"))
+}
+
+/// Run one line of input through the room's entity, after ingest-time redaction and under the
+/// room's timeout. Each line the entity emits is written to `out` immediately and recorded as
+/// its own OUTPUT entry; the caller is responsible for saving the room.
+pub fn stream_input(room: &mut Room, input: &str, now: i64, cfg: &Config, out: &mut dyn OutputSink) {
+    observe_idle_gap(room, cfg, now);
+    room.activate(now);
+    room.metadata.total_inputs += 1;
+
+    let input = redact::scrub_input(&cfg.redaction, input);
+    let entity = match room.entity() {
+        Ok(e) => e,
+        Err(e) => return out.emit(&e.to_string()),
+    };
+    let before = room.memory.next_seq();
+    let mut emitted = Emitted { out, lines: Vec::new(), bytes: 0, max: cfg.limits.max_output_size, omitted: 0 };
+    match invoke_entity(&*entity, room, &input, now, &mut emitted) {
+        // The input is discarded: the room is as it was before it, and what the entity
+        // emitted so far is not recorded.
+        Err(e) => {
+            let err = e.to_string();
+            let (metadata, banner) = match &e {
+                RoomError::EntityTimeout { seconds } => {
+                    room.metadata.total_timeouts += 1;
+                    (serde_json::json!({"timeout": seconds}),
+                        format!("{}\nOPERATION ABORTED\nENTITY RESTARTED\nSTATE: {:?} (recovered)", err, room.state))
+                }
+                _ => {
+                    let message = match &e {
+                        RoomError::EntityCrashed { message } => message.clone(),
+                        other => other.to_string(),
+                    };
+                    (serde_json::json!({"panic": message}),
+                        format!("{}\nATTEMPTING RECOVERY...\nENTITY REINITIALIZED\nSTATE: {:?}\nMEMORY: INTACT\nLAST INPUT: [discarded]", err, room.state))
+                }
+            };
+            room.memory.append(MemoryEntry{
+                seq: 0,
+                timestamp: now,
+                kind: EntryType::ERROR,
+                content: err.clone(),
+                metadata,
+                pinned: false,
+            });
+            room.metadata.total_errors += 1;
+            room.metadata.last_error = Some(err);
+            entity.restart(room, now);
+            if let Some(marker) = emitted.truncation_marker() {
+                emitted.out.send(marker);
+            }
+            for line in banner.lines() {
+                emitted.out.send(Output::new(OutputLevel::ERROR, line));
+            }
+        }
+        Ok(()) => {
+            count_entity_errors(room, before);
+            let marker = emitted.truncation_marker();
+            if let Some(m) = &marker {
+                emitted.out.send(m.clone());
+            }
+            for output in emitted.lines.into_iter().chain(marker) {
+                let mut metadata = serde_json::json!({"level": output.level});
+                if let Some(payload) = output.payload {
+                    metadata["payload"] = payload;
+                }
+                room.metadata.total_outputs += 1;
+                room.memory.append(MemoryEntry{
+                    seq: 0,
+                    timestamp: now,
+                    kind: EntryType::OUTPUT,
+                    content: output.text,
+                    metadata,
+                    pinned: false,
+                });
+            }
+        }
+    }
+
+    let every = room.config.checkpoint_interval;
+    if every > 0 && room.metadata.total_inputs.is_multiple_of(every) {
+        history::checkpoint(room, now);
+    }
+    enforce_memory_bounds(room, cfg, now, emitted.out);
+}
@@ -1,7 +1,10 @@
-use super::{Persistence, RoomSummary};
+use super::fsck::{self, FsckReport, IssueKind};
+use super::{checksum, Persistence, RoomSummary};
+use crate::memory::MemoryEntry;
+use crate::query::{self, MemoryQuery, SearchHit};
 use crate::room::{Room, RoomState};
 use anyhow::Context;
-use rusqlite::{params, Connection};
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, ToSql};
 use std::path::{Path, PathBuf};
 
 pub struct SqlitePersistence {
@@ -17,6 +20,16 @@ impl SqlitePersistence {
         Ok(Connection::open(&self.db_path)?)
     }
 
+    fn add_column_if_missing(conn: &Connection, table: &str, column: &str, decl: &str) -> anyhow::Result<()> {
+        let exists = conn
+            .prepare(&format!("SELECT 1 FROM pragma_table_info('{}') WHERE name = ?1", table))?
+            .exists(params![column])?;
+        if !exists {
+            conn.execute_batch(&format!("ALTER TABLE {} ADD COLUMN {} {};", table, column, decl))?;
+        }
+        Ok(())
+    }
+
     fn migrate(conn: &Connection) -> anyhow::Result<()> {
         conn.execute_batch(
             r#"
@@ -25,10 +38,89 @@ impl SqlitePersistence {
               state TEXT NOT NULL,
               created_at INTEGER NOT NULL,
               last_active INTEGER NOT NULL,
-              room_json TEXT NOT NULL
+              room_json TEXT NOT NULL,
+              checksum TEXT,
+              memory_revision INTEGER
             );
+            -- Row-per-entry mirror of each room's memory, keyed by entry sequence number.
+            CREATE TABLE IF NOT EXISTS memory (
+              room_id TEXT NOT NULL,
+              seq INTEGER NOT NULL,
+              timestamp INTEGER NOT NULL,
+              kind TEXT NOT NULL,
+              content TEXT NOT NULL,
+              metadata TEXT NOT NULL,
+              pinned INTEGER NOT NULL DEFAULT 0,
+              PRIMARY KEY (room_id, seq)
+            );
+            CREATE INDEX IF NOT EXISTS memory_room_ts ON memory (room_id, timestamp);
             "#,
         )?;
+        // Databases from before these columns existed.
+        Self::add_column_if_missing(conn, "rooms", "checksum", "TEXT")?;
+        Self::add_column_if_missing(conn, "rooms", "memory_revision", "INTEGER")?;
+        Self::add_column_if_missing(conn, "memory", "pinned", "INTEGER NOT NULL DEFAULT 0")?;
+
+        let has_fts = conn.prepare("SELECT 1 FROM sqlite_master WHERE name = 'memory_fts'")?.exists([])?;
+        conn.execute_batch(
+            r#"
+            CREATE VIRTUAL TABLE IF NOT EXISTS memory_fts USING fts5(content, content='memory', content_rowid='rowid');
+            CREATE TRIGGER IF NOT EXISTS memory_fts_insert AFTER INSERT ON memory BEGIN
+              INSERT INTO memory_fts (rowid, content) VALUES (new.rowid, new.content);
+            END;
+            CREATE TRIGGER IF NOT EXISTS memory_fts_delete AFTER DELETE ON memory BEGIN
+              INSERT INTO memory_fts (memory_fts, rowid, content) VALUES ('delete', old.rowid, old.content);
+            END;
+            CREATE TRIGGER IF NOT EXISTS memory_fts_update AFTER UPDATE ON memory BEGIN
+              INSERT INTO memory_fts (memory_fts, rowid, content) VALUES ('delete', old.rowid, old.content);
+              INSERT INTO memory_fts (rowid, content) VALUES (new.rowid, new.content);
+            END;
+            "#,
+        )?;
+        if !has_fts {
+            conn.execute_batch("INSERT INTO memory_fts (memory_fts) VALUES ('rebuild');")?;
+        }
+        Ok(())
+    }
+
+    /// Bring the `memory` rows for `room` in line with its store. Evicted entries are deleted
+    /// and new ones inserted; a changed `revision` means entries were rewritten, so all rows
+    /// are replaced.
+    fn sync_memory(tx: &rusqlite::Transaction, room: &Room, stored_revision: Option<i64>) -> anyhow::Result<()> {
+        let memory = &room.memory;
+        let first = memory.first_seq();
+        let max_seq: Option<i64> = tx.query_row("SELECT MAX(seq) FROM memory WHERE room_id = ?1", params![room.id], |r| r.get(0))?;
+        let from = match (stored_revision, max_seq) {
+            (Some(rev), Some(max)) if rev == memory.revision as i64 => {
+                tx.execute("DELETE FROM memory WHERE room_id = ?1 AND seq < ?2", params![room.id, first as i64])?;
+                (max as u64 + 1).max(first)
+            }
+            _ => {
+                tx.execute("DELETE FROM memory WHERE room_id = ?1", params![room.id])?;
+                first
+            }
+        };
+
+        let pending = memory.entries_from(from)?;
+        // Retention evicts from the middle of the log; find those rows only when counts disagree.
+        let mirrored: i64 = tx.query_row("SELECT COUNT(*) FROM memory WHERE room_id = ?1", params![room.id], |r| r.get(0))?;
+        if mirrored as u64 + pending.len() as u64 != memory.len() {
+            let held: std::collections::HashSet<u64> = memory.all_entries()?.iter().map(|e| e.seq).collect();
+            let rows: Vec<i64> = tx
+                .prepare("SELECT seq FROM memory WHERE room_id = ?1")?
+                .query_map(params![room.id], |r| r.get(0))?
+                .collect::<Result<_, _>>()?;
+            let mut delete = tx.prepare("DELETE FROM memory WHERE room_id = ?1 AND seq = ?2")?;
+            for seq in rows.into_iter().filter(|s| !held.contains(&(*s as u64))) {
+                delete.execute(params![room.id, seq])?;
+            }
+        }
+        let mut insert = tx.prepare(
+            "INSERT INTO memory (room_id, seq, timestamp, kind, content, metadata, pinned) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        )?;
+        for e in pending {
+            insert.execute(params![room.id, e.seq as i64, e.timestamp, format!("{:?}", e.kind), e.content, e.metadata.to_string(), e.pinned])?;
+        }
         Ok(())
     }
 }
@@ -71,22 +163,34 @@ impl Persistence for SqlitePersistence {
             params![id],
             |r| r.get(0),
         ).with_context(|| format!("room not found: {}", id))?;
-        Ok(serde_json::from_str(&raw)?)
+        let mut room: Room = serde_json::from_str(&raw)?;
+        room.memory.normalize_seqs()?;
+        room.config.normalize_compression();
+        Ok(room)
     }
 
     fn save_room(&self, room: &Room) -> anyhow::Result<()> {
         self.init()?;
-        let conn = self.conn()?;
+        let mut conn = self.conn()?;
         let raw = serde_json::to_string(room)?;
-        conn.execute(
-            "INSERT INTO rooms (id, state, created_at, last_active, room_json)
-             VALUES (?1, ?2, ?3, ?4, ?5)
+        let tx = conn.transaction()?;
+        let stored_revision: Option<i64> = tx
+            .query_row("SELECT memory_revision FROM rooms WHERE id = ?1", params![room.id], |r| r.get(0))
+            .optional()?
+            .flatten();
+        tx.execute(
+            "INSERT INTO rooms (id, state, created_at, last_active, room_json, checksum, memory_revision)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
              ON CONFLICT(id) DO UPDATE SET
                state=excluded.state,
                last_active=excluded.last_active,
-               room_json=excluded.room_json",
-            params![room.id, format!("{:?}", room.state), room.created_at, room.last_active, raw],
+               room_json=excluded.room_json,
+               checksum=excluded.checksum,
+               memory_revision=excluded.memory_revision",
+            params![room.id, format!("{:?}", room.state), room.created_at, room.last_active, raw, checksum(&raw), room.memory.revision as i64],
         )?;
+        Self::sync_memory(&tx, room, stored_revision)?;
+        tx.commit()?;
         Ok(())
     }
 
@@ -94,6 +198,148 @@ impl Persistence for SqlitePersistence {
         self.init()?;
         let conn = self.conn()?;
         conn.execute("DELETE FROM rooms WHERE id = ?1", params![id])?;
+        conn.execute("DELETE FROM memory WHERE room_id = ?1", params![id])?;
         Ok(())
     }
+
+    fn query_memory(&self, id: &str, q: &MemoryQuery) -> anyhow::Result<Vec<MemoryEntry>> {
+        self.init()?;
+        let conn = self.conn()?;
+        let mirrored: i64 = conn.query_row("SELECT COUNT(*) FROM memory WHERE room_id = ?1", params![id], |r| r.get(0))?;
+        if mirrored == 0 {
+            // Rooms saved before the memory table existed have no rows until their next save.
+            return self.load_room(id)?.memory.query(q);
+        }
+
+        // Type, time range and substring are pushed into SQL; regex and metadata predicates are not.
+        let mut sql = "SELECT seq, timestamp, kind, content, metadata, pinned FROM memory WHERE room_id = ?".to_string();
+        let mut args: Vec<Box<dyn ToSql>> = vec![Box::new(id.to_string())];
+        if !q.kinds.is_empty() {
+            sql.push_str(&format!(" AND kind IN ({})", vec!["?"; q.kinds.len()].join(", ")));
+            for k in &q.kinds {
+                args.push(Box::new(format!("{:?}", k)));
+            }
+        }
+        if let Some(t) = q.since {
+            sql.push_str(" AND timestamp >= ?");
+            args.push(Box::new(t));
+        }
+        if let Some(t) = q.until {
+            sql.push_str(" AND timestamp <= ?");
+            args.push(Box::new(t));
+        }
+        if let Some(s) = &q.contains {
+            sql.push_str(" AND instr(content, ?) > 0");
+            args.push(Box::new(s.clone()));
+        }
+        sql.push_str(if q.reverse { " ORDER BY seq DESC" } else { " ORDER BY seq ASC" });
+        if !q.needs_post_filter() {
+            sql.push_str(" LIMIT ? OFFSET ?");
+            args.push(Box::new(q.limit.map(|l| l as i64).unwrap_or(-1)));
+            args.push(Box::new(q.offset as i64));
+        }
+
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map(params_from_iter(args.iter()), |r| {
+            Ok((r.get::<_, i64>(0)?, r.get::<_, i64>(1)?, r.get::<_, String>(2)?, r.get::<_, String>(3)?, r.get::<_, String>(4)?, r.get::<_, bool>(5)?))
+        })?;
+        let mut hits = vec![];
+        for row in rows {
+            let (seq, timestamp, kind, content, metadata, pinned) = row?;
+            hits.push(MemoryEntry{
+                seq: seq as u64,
+                timestamp,
+                kind: query::parse_entry_type(&kind)?,
+                content,
+                metadata: serde_json::from_str(&metadata)?,
+                pinned,
+            });
+        }
+        if q.needs_post_filter() {
+            hits.retain(|e| q.matches(e));
+            return Ok(q.page(hits));
+        }
+        Ok(hits)
+    }
+
+    fn search_memory(&self, id: &str, text: &str, limit: usize) -> anyhow::Result<Vec<SearchHit>> {
+        self.init()?;
+        let conn = self.conn()?;
+        let mirrored: i64 = conn.query_row("SELECT COUNT(*) FROM memory WHERE room_id = ?1", params![id], |r| r.get(0))?;
+        let terms = query::tokenize(text);
+        if terms.is_empty() {
+            return Ok(vec![]);
+        }
+        if mirrored == 0 {
+            let memory = self.load_room(id)?.memory;
+            let entries = memory.all_entries()?;
+            return Ok(query::rank(entries.iter().map(|e| (e.seq, e)), &terms, limit));
+        }
+        // Quote every term so user input is never parsed as FTS5 query syntax; terms are ANDed.
+        let expr = terms.iter().map(|t| format!("\"{}\"", t)).collect::<Vec<_>>().join(" ");
+        let mut stmt = conn.prepare(
+            "SELECT m.seq, m.timestamp, m.kind, snippet(memory_fts, 0, '[', ']', '...', 10), bm25(memory_fts)
+             FROM memory_fts JOIN memory m ON m.rowid = memory_fts.rowid
+             WHERE memory_fts MATCH ?1 AND m.room_id = ?2
+             ORDER BY bm25(memory_fts), m.seq DESC
+             LIMIT ?3",
+        )?;
+        let rows = stmt.query_map(params![expr, id, limit as i64], |r| {
+            Ok((r.get::<_, i64>(0)?, r.get::<_, i64>(1)?, r.get::<_, String>(2)?, r.get::<_, String>(3)?, r.get::<_, f64>(4)?))
+        })?;
+        let mut hits = vec![];
+        for row in rows {
+            let (seq, timestamp, kind, snippet, rank) = row?;
+            // bm25() is lower-is-better; flip it so every backend ranks higher scores first.
+            hits.push(SearchHit { seq: seq as u64, timestamp, kind: query::parse_entry_type(&kind)?, snippet, score: -rank });
+        }
+        Ok(hits)
+    }
+
+    fn fsck(&self, repair: bool) -> anyhow::Result<FsckReport> {
+        self.init()?;
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT id, room_json, checksum FROM rooms")?;
+        let rows: Vec<(String, String, Option<String>)> = stmt
+            .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))?
+            .collect::<Result<_, _>>()?;
+
+        let mut report = FsckReport::default();
+        for (id, raw, stored) in rows {
+            report.rooms_checked += 1;
+            let mut room: Room = match serde_json::from_str(&raw) {
+                Ok(room) => room,
+                Err(e) => {
+                    report.push(&id, IssueKind::DESERIALIZE_FAILED, e.to_string(), false);
+                    continue;
+                }
+            };
+            if room.id != id {
+                report.push(&id, IssueKind::ID_MISMATCH, format!("row id={} room.id={}", id, room.id), false);
+                continue;
+            }
+
+            // A checksum mismatch disables repair for this row.
+            let mut can_repair = repair;
+            let mut changed = false;
+            match stored {
+                Some(stored) if stored == checksum(&raw) => {}
+                Some(stored) => {
+                    report.push(&id, IssueKind::CHECKSUM_MISMATCH,
+                        format!("stored={} actual={}", stored, checksum(&raw)), false);
+                    can_repair = false;
+                }
+                None => {
+                    report.push(&id, IssueKind::CHECKSUM_MISSING, "checksum column is NULL", repair);
+                    changed = repair;
+                }
+            }
+
+            changed |= fsck::check_room(&mut room, can_repair, &mut report);
+            if changed {
+                self.save_room(&room)?;
+            }
+        }
+        Ok(report)
+    }
 }
@@ -0,0 +1,73 @@
+use crate::memory::MemoryStore;
+use crate::query::{self, SearchHit};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Entries allowed past the end of the index before a save rebuilds it anyway.
+const MAX_UNINDEXED: u64 = 1024;
+
+/// Inverted index from term to entry sequence numbers, kept next to a room on disk.
+///
+/// Evicted entries need no rewrite: their sequence numbers no longer resolve to a held entry
+/// and are skipped at search time. Entries appended since the build are scanned directly.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TextIndex {
+    pub revision: u64,
+    /// `MemoryStore::compactions` at build time.
+    pub compacted_at: u64,
+    /// Sequence number one past the last indexed entry.
+    pub indexed_upto: u64,
+    pub postings: BTreeMap<String, Vec<u64>>,
+}
+
+impl TextIndex {
+    pub fn build(memory: &MemoryStore) -> anyhow::Result<Self> {
+        let mut postings: BTreeMap<String, Vec<u64>> = BTreeMap::new();
+        for e in memory.all_entries()? {
+            for term in query::tokenize(&e.content) {
+                let list = postings.entry(term).or_default();
+                if list.last() != Some(&e.seq) {
+                    list.push(e.seq);
+                }
+            }
+        }
+        Ok(Self {
+            revision: memory.revision,
+            compacted_at: memory.compactions,
+            indexed_upto: memory.next_seq(),
+            postings,
+        })
+    }
+
+    /// True once memory was compacted or rewritten since the build, or has outgrown the index.
+    pub fn is_stale(&self, memory: &MemoryStore) -> bool {
+        self.revision != memory.revision
+            || self.compacted_at != memory.compactions
+            || memory.next_seq().saturating_sub(self.indexed_upto) > MAX_UNINDEXED
+    }
+
+    pub fn search(&self, memory: &MemoryStore, text: &str, limit: usize) -> anyhow::Result<Vec<SearchHit>> {
+        let terms = query::tokenize(text);
+        if terms.is_empty() {
+            return Ok(vec![]);
+        }
+        let first = memory.first_seq();
+        let next = memory.next_seq();
+
+        let mut candidates: Option<BTreeSet<u64>> = None;
+        for t in &terms {
+            let seqs: BTreeSet<u64> = self.postings.get(t).into_iter().flatten().copied().collect();
+            candidates = Some(match candidates {
+                None => seqs,
+                Some(c) => c.intersection(&seqs).copied().collect(),
+            });
+        }
+        let mut seqs: BTreeSet<u64> = candidates.unwrap_or_default().into_iter().filter(|s| *s >= first && *s < next).collect();
+        seqs.extend(self.indexed_upto.max(first)..next);
+
+        let Some(&from) = seqs.first() else { return Ok(vec![]) };
+        let held = memory.entries_from(from)?;
+        let selected = held.iter().filter(|e| seqs.contains(&e.seq)).map(|e| (e.seq, e));
+        Ok(query::rank(selected, &terms, limit))
+    }
+}